@@ -0,0 +1,92 @@
+//! Per-event overhead of recording from many threads at once
+//! Compares the per-thread buffers of scoper with a single global `Mutex<Vec>`
+//!
+//! Run with `cargo bench --bench contention`
+
+use std::{
+    hint::black_box,
+    path::Path,
+    sync::{Barrier, Mutex},
+    thread::{ThreadId, current},
+    time::{Duration, Instant},
+};
+
+use scoper::{Info, RecordScope, TraceInfo, record_custom_scope};
+
+const EVENTS_PER_THREAD: usize = 20_000;
+const THREAD_COUNTS: [usize; 4] = [1, 4, 16, 32];
+
+static INFO: Info = &TraceInfo::new("bench").with_category("bench");
+
+/// The previous design, every event takes the same lock
+static GLOBAL: Mutex<Vec<(ThreadId, Instant, Instant, Info)>> = Mutex::new(Vec::new());
+
+fn global_mutex(threads: usize) -> Duration
+{
+    let duration = run_threads(threads, || {
+        for _ in 0..EVENTS_PER_THREAD
+        {
+            let start = Instant::now();
+            let end = Instant::now();
+            GLOBAL.lock().unwrap().push((current().id(), start, end, INFO));
+        }
+    });
+    black_box(std::mem::take(&mut *GLOBAL.lock().unwrap()));
+    duration
+}
+
+fn per_thread_buffers(threads: usize) -> Duration
+{
+    let record = RecordScope::start(Path::new("results/bench_contention.json"));
+    let session = record.session();
+    run_threads(threads, || {
+        let _session = session.enter();
+        for _ in 0..EVENTS_PER_THREAD
+        {
+            let start = Instant::now();
+            let end = Instant::now();
+            record_custom_scope(INFO, start, end);
+        }
+    })
+}
+
+/// Time until all threads pushed their events, not counting the spawning
+fn run_threads(threads: usize, work: impl Fn() + Sync) -> Duration
+{
+    let barrier = Barrier::new(threads + 1);
+    std::thread::scope(|s| {
+        for _ in 0..threads
+        {
+            s.spawn(|| {
+                barrier.wait();
+                work();
+                barrier.wait();
+            });
+        }
+        barrier.wait();
+        let start = Instant::now();
+        barrier.wait();
+        start.elapsed()
+    })
+}
+
+/// Core time spent per event, stays flat as long as the threads do not slow each other down
+#[allow(clippy::cast_precision_loss)]
+fn per_event(duration: Duration, threads: usize) -> f64
+{
+    let cores = std::thread::available_parallelism().map_or(1, usize::from).min(threads);
+    duration.as_secs_f64() * 1e9 * cores as f64 / (threads * EVENTS_PER_THREAD) as f64
+}
+
+fn main()
+{
+    std::fs::create_dir_all("results").unwrap();
+    println!("{:>8} {:>22} {:>22}", "threads", "global mutex ns/event", "per thread ns/event");
+    println!("{:>8} {:>22} {:>22}", "", "(core time)", "(core time)");
+    for threads in THREAD_COUNTS
+    {
+        let global = global_mutex(threads);
+        let local = per_thread_buffers(threads);
+        println!("{threads:>8} {:>22.1} {:>22.1}", per_event(global, threads), per_event(local, threads));
+    }
+}
@@ -1,6 +1,6 @@
 mod value;
 
-pub use value::Value;
+pub use value::{Args, Value};
 
 pub type Info = &'static TraceInfo<'static>;
 pub struct TraceInfo<'a>
@@ -13,40 +13,32 @@ pub struct TraceInfo<'a>
 
 impl<'a> TraceInfo<'a>
 {
-    #[allow(clippy::new_without_default)]
-    pub fn new() -> Self
+    /// Info with only a name, usable in `const` and `static` items
+    /// `trace_info!` also fills in the category from the module path
+    #[must_use]
+    pub const fn new(name: &'a str) -> Self
     {
-        Self
-        {
-            name: "",
+        Self {
+            name,
             category: "",
             header: "",
             args: "",
         }
     }
 
-    pub fn name(self, _name: &'a str) -> Self
-    {
-        self
-    }
-
-    pub fn category(self, _category: &'a str) -> Self
-    {
-        self
-    }
+    #[must_use]
+    pub const fn with_category(self, category: &'a str) -> Self { Self { category, ..self } }
 
-    pub fn header(self, _header: &'a str) -> Self
-    {
-        self
-    }
+    #[must_use]
+    pub const fn with_header(self, header: &'a str) -> Self { Self { header, ..self } }
 
-    pub fn args(self, _args: &'a str) -> Self
-    {
-        self
-    }
+    /// Static args, a json object string is merged into the args of the event
+    #[must_use]
+    pub const fn with_args(self, args: &'a str) -> Self { Self { args, ..self } }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Ord, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum InstantScopeSize
 {
     Thread,
@@ -67,3 +59,28 @@ impl InstantScopeSize
         }
     }
 }
+
+/// Identifies an async span
+/// Ids are only unique within the category of the span, [`AsyncId::Global`] ids are unique across processes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AsyncId
+{
+    Id(u64),
+    Local(u64),
+    Global(u64),
+}
+
+impl From<u64> for AsyncId
+{
+    fn from(id: u64) -> Self { AsyncId::Id(id) }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AsyncPhase
+{
+    Begin,
+    Step,
+    End,
+}
@@ -1,11 +1,17 @@
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value
 {
     UInt(u64),
     IInt(i64),
     Float(f64),
-    //Other(Box<String>)
+    Bool(bool),
+    Str(String),
 }
 
+/// Runtime arguments of an event
+pub type Args = Vec<(&'static str, Value)>;
+
 macro_rules! FromForValue {
     ($version: ident, $typ1:ty, $( $typ:ty),+) => {
         impl From<$typ1> for Value
@@ -27,4 +33,19 @@ macro_rules! FromForValue {
 
 FromForValue!(Float, f64, f32);
 FromForValue!(UInt, u64, u32, u16, u8, usize);
-FromForValue!(IInt, i64, i32, i16, i8, isize);
\ No newline at end of file
+FromForValue!(IInt, i64, i32, i16, i8, isize);
+
+impl From<bool> for Value
+{
+    fn from(value: bool) -> Self { Value::Bool(value) }
+}
+
+impl From<String> for Value
+{
+    fn from(value: String) -> Self { Value::Str(value) }
+}
+
+impl From<&str> for Value
+{
+    fn from(value: &str) -> Self { Value::Str(value.to_string()) }
+}
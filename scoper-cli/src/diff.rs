@@ -0,0 +1,143 @@
+use std::{
+    io::{self, Write},
+    process::ExitCode,
+};
+
+use scoper_impl::{Metric, ScopeDiff, Trace};
+use serde_json::{Value as JsonValue, json};
+
+use crate::{Args, Result};
+
+/// Columns of the text report
+const COLUMNS: [Metric; 5] = [Metric::Count, Metric::Total, Metric::SelfTime, Metric::P50, Metric::P95];
+
+/// Compares the scopes of two traces, fails with exit code 2 if one of them regressed by more than `--threshold` percent
+pub(crate) fn diff(before: &str, after: &str, args: &Args) -> Result<ExitCode>
+{
+    let metric: Metric = args.option("metric").unwrap_or("total").parse()?;
+    let threshold = args
+        .option("threshold")
+        .map(|percent| percent.trim_end_matches('%').parse::<f64>().map_err(|_| format!("--threshold expects percent, not {percent}")))
+        .transpose()?;
+    let min = if metric == Metric::Count
+    {
+        let calls = args.option("min-count").map(|calls| calls.parse::<u64>().map_err(|_| format!("--min-count expects calls, not {calls}")));
+        calls.transpose()?.unwrap_or_default()
+    }
+    else
+    {
+        args.millis("min-ms")?.unwrap_or_default()
+    };
+    let diffs = scoper_impl::diff(&Trace::open(before)?, &Trace::open(after)?, metric);
+    let regressed: Vec<_> = diffs
+        .iter()
+        .filter(|diff| threshold.is_some_and(|threshold| diff.regressed(metric, threshold, min)))
+        .map(|diff| diff.name.as_str())
+        .collect();
+
+    let mut out = io::stdout().lock();
+    match args.option("format").unwrap_or("text")
+    {
+        "text" => text(&mut out, &diffs, &regressed, metric, threshold)?,
+        "json" =>
+        {
+            let mut report = json!({
+                "metric": metric.name(),
+                "threshold_percent": threshold,
+                "regressed": regressed,
+                "scopes": diffs.iter().map(scope_json).collect::<Vec<_>>(),
+            });
+            if metric == Metric::Count
+            {
+                report["min_count"] = min.into();
+            }
+            else
+            {
+                report["min_ms"] = millis(min).into();
+            }
+            writeln!(out, "{report:#}")?;
+        },
+        other => return Err(format!("Unknown format {other}, use text or json").into()),
+    }
+    Ok(if regressed.is_empty() { ExitCode::SUCCESS } else { ExitCode::from(2) })
+}
+
+fn text(out: &mut impl Write, diffs: &[ScopeDiff], regressed: &[&str], metric: Metric, threshold: Option<f64>) -> io::Result<()>
+{
+    let width = diffs.iter().map(|diff| diff.name.len()).chain([4]).max().unwrap_or_default();
+    write!(out, "  {:<width$}", "name")?;
+    for column in COLUMNS
+    {
+        let title = if column == Metric::Count { column.name().to_string() } else { format!("{} ms", column.name()) };
+        write!(out, " {title:>24}")?;
+    }
+    writeln!(out)?;
+    for diff in diffs
+    {
+        let mark = if regressed.contains(&diff.name.as_str()) { '!' } else { ' ' };
+        write!(out, "{mark} {:<width$}", diff.name.replace('\n', " "))?;
+        for column in COLUMNS
+        {
+            write!(out, " {:>24}", change(diff, column))?;
+        }
+        writeln!(out)?;
+    }
+    if let Some(threshold) = threshold
+    {
+        writeln!(out, "\n{} scopes grew by more than {threshold}% in {}", regressed.len(), metric.name())?;
+    }
+    Ok(())
+}
+
+/// Like `+1.250 (+12.5%)`
+fn change(diff: &ScopeDiff, metric: Metric) -> String
+{
+    match (&diff.before, &diff.after)
+    {
+        (None, _) => "new".to_string(),
+        (_, None) => "gone".to_string(),
+        _ =>
+        {
+            let delta = diff.delta(metric);
+            let delta = if metric == Metric::Count { format!("{delta:+}") } else { format!("{:+.3}", signed_millis(delta)) };
+            match diff.relative(metric)
+            {
+                Some(relative) => format!("{delta} ({:+.1}%)", relative * 100.0),
+                None => delta,
+            }
+        },
+    }
+}
+
+fn scope_json(diff: &ScopeDiff) -> JsonValue
+{
+    let status = match (&diff.before, &diff.after)
+    {
+        (None, _) => "new",
+        (_, None) => "gone",
+        _ => "changed",
+    };
+    let mut json = json!({ "name": diff.name, "status": status });
+    for metric in Metric::ALL
+    {
+        let (before, after) = diff.values(metric);
+        let (key, values) = if metric == Metric::Count
+        {
+            (metric.name().to_string(), json!({ "before": before, "after": after, "delta": diff.delta(metric) }))
+        }
+        else
+        {
+            let values = json!({ "before": millis(before), "after": millis(after), "delta": signed_millis(diff.delta(metric)) });
+            (format!("{}_ms", metric.name()), values)
+        };
+        json[&key] = values;
+        json[&key]["relative"] = json!(diff.relative(metric));
+    }
+    json
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn millis(nanos: u64) -> f64 { nanos as f64 / 1e6 }
+
+#[allow(clippy::cast_precision_loss)]
+fn signed_millis(nanos: i128) -> f64 { nanos as f64 / 1e6 }
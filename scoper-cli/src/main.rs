@@ -0,0 +1,306 @@
+#![warn(clippy::all, clippy::perf, clippy::pedantic)]
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    io::{self, Write},
+    path::Path,
+    process::ExitCode,
+    time::Duration,
+};
+
+use scoper_impl::{ScopeStats, Trace, convert};
+
+mod diff;
+
+const USAGE: &str = "Usage: scoper <command> <args>
+
+Commands:
+  summary <trace> [--sort total|self|count|mean|max]
+      Count, total, self, mean, p50, p95 and max time of every scope name
+  merge <output> <trace>...
+      Combines the traces into one, every file becomes its own process named by its position and file name
+  convert <input> <output>
+      Writes the input in the format picked by the extension of the output
+  filter <input> <output> [--category <category>] [--thread <id or name>] [--from <ms>] [--to <ms>]
+      Keeps the events of a category, thread or time window
+  diff <before> <after> [--metric count|total|self|mean|p50|p95|max] [--threshold <percent>] [--min-ms <ms>] [--min-count <calls>]
+       [--format text|json]
+      Changes of every scope name, the biggest regression of the metric (total) first
+      Exits with 2 if the metric of a scope grew by more than the threshold and at least min-ms, or min-count calls for count
+
+Json and binary (.scoper) traces can be read, .gz and .zst are decompressed";
+
+pub(crate) type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+fn main() -> ExitCode
+{
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args)
+    {
+        Ok(code) => code,
+        // Like piping into `head`
+        Err(err) if err.downcast_ref::<io::Error>().is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe) => ExitCode::SUCCESS,
+        Err(err) =>
+        {
+            eprintln!("scoper: {err}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+fn run(args: &[String]) -> Result<ExitCode>
+{
+    let Some((command, args)) = args.split_first()
+    else
+    {
+        println!("{USAGE}");
+        return Ok(ExitCode::SUCCESS);
+    };
+    let args = Args::parse(args)?;
+    match (command.as_str(), args.positional.as_slice())
+    {
+        ("summary", [trace]) => summary(&Trace::open(trace)?, args.option("sort").unwrap_or("total")),
+        ("merge", [output, traces @ ..]) if !traces.is_empty() => merge(output, traces),
+        ("convert", [input, output]) => Ok(convert(input, output)?),
+        ("filter", [input, output]) => filter(input, output, &args),
+        ("diff", [before, after]) => return diff::diff(before, after, &args),
+        ("help" | "--help" | "-h", []) =>
+        {
+            println!("{USAGE}");
+            Ok(())
+        },
+        _ => Err(format!("Unexpected arguments for {command}\n\n{USAGE}").into()),
+    }
+    .map(|()| ExitCode::SUCCESS)
+}
+
+/// Positional arguments and `--name value` options of a command
+pub(crate) struct Args
+{
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args
+{
+    fn parse(args: &[String]) -> Result<Self>
+    {
+        let mut parsed = Self {
+            positional: Vec::new(),
+            options: HashMap::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next()
+        {
+            if let Some(name) = arg.strip_prefix("--")
+            {
+                let (name, value) = match name.split_once('=')
+                {
+                    Some((name, value)) => (name, value.to_string()),
+                    None => (name, args.next().ok_or_else(|| format!("Missing value of --{name}"))?.clone()),
+                };
+                parsed.options.insert(name.to_string(), value);
+            }
+            else
+            {
+                parsed.positional.push(arg.clone());
+            }
+        }
+        Ok(parsed)
+    }
+
+    pub(crate) fn option(&self, name: &str) -> Option<&str> { self.options.get(name).map(String::as_str) }
+
+    /// Option given in milliseconds as nanoseconds
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub(crate) fn millis(&self, name: &str) -> Result<Option<u64>>
+    {
+        let Some(value) = self.option(name)
+        else
+        {
+            return Ok(None);
+        };
+        let millis: f64 = value.parse().map_err(|_| format!("--{name} expects milliseconds, not {value}"))?;
+        Ok(Some((millis * 1e6).max(0.0) as u64))
+    }
+}
+
+fn summary(trace: &Trace, sort: &str) -> Result<()>
+{
+    let mut stats = trace.scope_stats();
+    let key: fn(&ScopeStats) -> Duration = match sort
+    {
+        "total" => |stats| stats.total,
+        "self" => |stats| stats.self_time,
+        "mean" => |stats| stats.mean,
+        "max" => |stats| stats.max,
+        "count" => |stats| Duration::from_nanos(stats.count as u64),
+        other => return Err(format!("Unknown sort {other}, use total, self, count, mean or max").into()),
+    };
+    stats.sort_by_key(|stats| std::cmp::Reverse(key(stats)));
+
+    let width = stats.iter().map(|stats| stats.name.len()).chain([4]).max().unwrap_or_default();
+    let mut out = io::stdout().lock();
+    writeln!(
+        out,
+        "{:<width$} {:>8} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12}",
+        "name", "count", "total ms", "self ms", "mean ms", "p50 ms", "p95 ms", "max ms"
+    )?;
+    for stats in &stats
+    {
+        writeln!(
+            out,
+            "{:<width$} {:>8} {:>12.3} {:>12.3} {:>12.3} {:>12.3} {:>12.3} {:>12.3}",
+            stats.name.replace('\n', " "),
+            stats.count,
+            millis(stats.total),
+            millis(stats.self_time),
+            millis(stats.mean),
+            millis(stats.p50),
+            millis(stats.p95),
+            millis(stats.max)
+        )?;
+    }
+    Ok(())
+}
+
+fn millis(duration: Duration) -> f64 { duration.as_secs_f64() * 1000.0 }
+
+fn merge(output: &str, traces: &[String]) -> Result<()>
+{
+    let mut merged: Option<Trace> = None;
+    for (index, path) in traces.iter().enumerate()
+    {
+        let mut trace = Trace::open(path)?;
+        // Same headers of different files stay apart, the position keeps files of the same name apart as well
+        let name = Path::new(path).file_name().and_then(|name| name.to_str()).unwrap_or(path);
+        let file = format!("{} {name}", index + 1);
+        trace.rename_headers(|header| if header.is_empty() { file.clone() } else { format!("{file}/{header}") });
+        match &mut merged
+        {
+            Some(merged) => merged.merge(trace),
+            None => merged = Some(trace),
+        }
+    }
+    Ok(merged.ok_or("Nothing to merge")?.write(output)?)
+}
+
+fn filter(input: &str, output: &str, args: &Args) -> Result<()>
+{
+    let mut trace = Trace::open(input)?;
+    if let Some(category) = args.option("category")
+    {
+        // The macros separate the module path by commas, it can be given with `::` as well
+        let module = format!("{category}::");
+        trace.retain(|event| {
+            event.category.split(',').any(|part| part == category) || format!("{}::", event.category.replace(',', "::")).starts_with(&module)
+        });
+    }
+    if let Some(thread) = args.option("thread")
+    {
+        let threads: BTreeSet<_> = trace.events().iter().map(|event| (event.header.clone(), event.thread)).collect();
+        let threads: BTreeSet<_> = threads
+            .into_iter()
+            .filter(|(header, id)| thread.parse() == Ok(*id) || trace.thread_name(header, *id) == thread)
+            .collect();
+        trace.retain(|event| threads.contains(&(event.header.clone(), event.thread)));
+    }
+    let (from, to) = (args.millis("from")?, args.millis("to")?);
+    if from.is_some() || to.is_some()
+    {
+        trace.retain(|event| from.is_none_or(|from| event.end() >= from) && to.is_none_or(|to| event.time <= to));
+    }
+    Ok(trace.write(output)?)
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::{collections::BTreeSet, path::PathBuf, process::ExitCode};
+
+    use scoper_impl::Trace;
+    use serde_json::json;
+
+    use crate::run;
+
+    /// Chrome trace of complete scopes given as (name, start, duration) in microseconds
+    fn write_trace(file: &str, scopes: &[(&str, u64, u64)]) -> String
+    {
+        let events: Vec<_> = scopes
+            .iter()
+            .map(|(name, ts, dur)| json!({ "name": name, "cat": "test", "ph": "X", "pid": "cli", "tid": 1, "ts": ts, "dur": dur }))
+            .collect();
+        let path = results().join(file);
+        std::fs::write(&path, json!({ "traceEvents": events }).to_string()).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn results() -> PathBuf
+    {
+        let path = std::env::temp_dir().join(format!("scoper-cli-{}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn scoper(args: &[&str]) -> ExitCode { run(&args.iter().map(ToString::to_string).collect::<Vec<_>>()).unwrap() }
+
+    #[test]
+    fn diff_test()
+    {
+        let before = write_trace("diff_before.json", &[("physics", 0, 1000)]);
+        let after = write_trace("diff_after.json", &[("physics", 0, 2000), ("physics", 3000, 2000)]);
+
+        assert_eq!(scoper(&["diff", &before, &after]), ExitCode::SUCCESS);
+        assert_eq!(scoper(&["diff", &before, &after, "--threshold", "50"]), ExitCode::from(2));
+        assert_eq!(scoper(&["diff", &before, &after, "--threshold", "50", "--min-ms", "3"]), ExitCode::from(2));
+        assert_eq!(scoper(&["diff", &before, &after, "--threshold", "50", "--min-ms", "3.5"]), ExitCode::SUCCESS);
+        assert_eq!(scoper(&["diff", &before, &before, "--threshold", "0", "--format", "json"]), ExitCode::SUCCESS);
+
+        // One more call, the minimum of the count is given in calls
+        assert_eq!(scoper(&["diff", &before, &after, "--metric", "count", "--threshold", "50", "--min-ms", "1"]), ExitCode::from(2));
+        assert_eq!(scoper(&["diff", &before, &after, "--metric", "count", "--threshold", "50", "--min-count", "1"]), ExitCode::from(2));
+        assert_eq!(scoper(&["diff", &before, &after, "--metric", "count", "--threshold", "50", "--min-count", "2"]), ExitCode::SUCCESS);
+    }
+
+    #[test]
+    fn merge_test()
+    {
+        std::fs::create_dir_all(results().join("other")).unwrap();
+        let first = write_trace("run.1.json", &[("physics", 0, 1000)]);
+        let second = write_trace("run.2.json", &[("render", 0, 1000)]);
+        let same_name = write_trace("other/run.1.json", &[("audio", 0, 1000)]);
+        let output = results().join("merged.json");
+        let output = output.to_str().unwrap();
+
+        assert_eq!(scoper(&["merge", output, &first, &second, &same_name]), ExitCode::SUCCESS);
+        let merged = Trace::open(output).unwrap();
+        let headers: BTreeSet<_> = merged.events().iter().map(|event| (event.header.to_string(), event.name.to_string())).collect();
+        assert_eq!(
+            headers,
+            BTreeSet::from([
+                ("1 run.1.json/cli".to_string(), "physics".to_string()),
+                ("2 run.2.json/cli".to_string(), "render".to_string()),
+                ("3 run.1.json/cli".to_string(), "audio".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn filter_test()
+    {
+        let input = write_trace("filter_input.json", &[("physics", 0, 1000), ("render", 2000, 1000), ("audio", 5000, 1000)]);
+        let output = results().join("filtered.json");
+        let output = output.to_str().unwrap();
+        let names = |args: &[&str]| {
+            assert_eq!(scoper(&[&["filter", &input, output], args].concat()), ExitCode::SUCCESS);
+            Trace::open(output).unwrap().events().iter().map(|event| event.name.to_string()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(&["--from", "1.5", "--to", "4"]), ["render"]);
+        assert_eq!(names(&["--to", "2"]), ["physics", "render"]);
+        assert_eq!(names(&["--category", "test", "--thread", "1"]), ["physics", "render", "audio"]);
+        assert!(names(&["--category", "other"]).is_empty());
+        assert!(names(&["--thread", "2"]).is_empty());
+    }
+}
@@ -2,10 +2,12 @@
 
 extern crate proc_macro;
 
-use crate::proc_macro::TokenStream;
+use crate::proc_macro::{TokenStream, TokenTree};
 
 /// Adds the function scope to the scoper recording
 /// Takes optional header attribute
+/// Flows bind to the function scope with `flow_in = expr`, `flow_out = expr` or `flow_step = expr`
+/// Any other `key = expr` is attached as argument to the scope
 /// # Panics
 ///
 /// Panics if not used with functions
@@ -27,13 +29,25 @@ pub fn record(attr: TokenStream, input: TokenStream) -> TokenStream
         let gname = generics.to_string();
     }*/
 
+    let Attributes { header, args, flows } = parse_attributes(attr);
+    let args = args.iter().map(|(key, expr)| quote::quote!(, #key = #expr));
+    let flows = if flows.is_empty()
+    {
+        quote::quote!()
+    }
+    else
+    {
+        let flows = flows.iter().map(|(kind, expr)| quote::quote!(#kind = #expr));
+        quote::quote!(; #(#flows),*)
+    };
+
     let mut ext: syn::ItemFn = 
-    if let Some(header) = header(attr)
+    if let Some(header) = header
     {
         syn::parse_quote! {
             fn bla()
             {
-                record_scope!(#header, #name);
+                record_scope!(#header, #name #(#args)* #flows);
             }
         }
     }
@@ -42,7 +56,7 @@ pub fn record(attr: TokenStream, input: TokenStream) -> TokenStream
         syn::parse_quote! {
             fn bla()
             {
-                record_scope!(#name);
+                record_scope!(#name #(#args)* #flows);
             }
         }
     };
@@ -53,6 +67,88 @@ pub fn record(attr: TokenStream, input: TokenStream) -> TokenStream
     quote::quote!(#input).into()
 }
 
+/// Implements `TracedObject` so the type can be wrapped in `Traced`
+/// The objects are named after the type
+/// # Panics
+///
+/// Panics if not used with types
+#[proc_macro_derive(TracedObject)]
+pub fn traced_object(input: TokenStream) -> TokenStream
+{
+    let input: syn::DeriveInput = syn::parse2(input.into()).expect("Use #[derive(TracedObject)] only on types.");
+    let ident = &input.ident;
+    let name = ident.to_string();
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    quote::quote! {
+        impl #impl_generics scoper::TracedObject for #ident #type_generics #where_clause
+        {
+            const INFO: scoper::Info = &scoper::macros::trace_info!(#name);
+        }
+    }
+    .into()
+}
+
+struct Attributes
+{
+    header: Option<String>,
+    args: Vec<(syn::Ident, syn::Expr)>,
+    flows: Vec<(syn::Ident, syn::Expr)>,
+}
+
+fn parse_attributes(attr: TokenStream) -> Attributes
+{
+    let mut attributes = Attributes {
+        header: None,
+        args: Vec::new(),
+        flows: Vec::new(),
+    };
+    let mut segments = vec![Vec::new()];
+    for token in attr
+    {
+        match &token
+        {
+            TokenTree::Punct(punct) if punct.as_char() == ',' => segments.push(Vec::new()),
+            _ => segments.last_mut().unwrap().push(token),
+        }
+    }
+
+    for segment in segments.into_iter().filter(|segment| !segment.is_empty())
+    {
+        if let Some((key, expr)) = key_value(&segment)
+        {
+            if ["flow_in", "flow_out", "flow_step"].contains(&key.to_string().as_str())
+            {
+                attributes.flows.push((key, expr));
+            }
+            else
+            {
+                attributes.args.push((key, expr));
+            }
+        }
+        else
+        {
+            attributes.header = header(segment.into_iter().collect());
+        }
+    }
+    attributes
+}
+
+fn key_value(segment: &[TokenTree]) -> Option<(syn::Ident, syn::Expr)>
+{
+    match segment
+    {
+        [TokenTree::Ident(key), TokenTree::Punct(eq), expr @ ..] if eq.as_char() == '=' =>
+        {
+            let key = syn::Ident::new(&key.to_string(), key.span().into());
+            let expr = syn::parse2(expr.iter().cloned().collect::<TokenStream>().into()).expect("Expected an expression after `=`.");
+            Some((key, expr))
+        },
+        _ => None,
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
 fn header(attr: TokenStream) -> Option<String>
 {
     if attr.is_empty()
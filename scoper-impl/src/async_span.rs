@@ -0,0 +1,57 @@
+use scoper_base::{Args, AsyncId, AsyncPhase, Info};
+
+use crate::{
+    session::{self, Active},
+    types::{AsyncEvent, BaseInfo, Trace},
+};
+
+/// Span that may start and end on different threads
+/// Emits the begin event on creation and the end event when dropped
+/// The events end up in the session that was active when the span began
+pub struct AsyncSpan
+{
+    info: Info,
+    id: AsyncId,
+    session: Option<Active>,
+}
+
+impl AsyncSpan
+{
+    #[must_use]
+    pub fn begin(info: Info, id: impl Into<AsyncId>) -> Self { Self::begin_with_args(info, id, Args::new()) }
+
+    /// Begins the span, `args` are attached to the begin event
+    #[must_use]
+    pub fn begin_with_args(info: Info, id: impl Into<AsyncId>, args: Args) -> Self
+    {
+        let span = Self {
+            info,
+            id: id.into(),
+            session: session::active(),
+        };
+        span.push(info, AsyncPhase::Begin, args);
+        span
+    }
+
+    /// Marks an intermediate step of the span
+    pub fn step(&self, info: Info) { self.step_with_args(info, Args::new()); }
+
+    /// Marks an intermediate step of the span, `args` are attached to the step event
+    pub fn step_with_args(&self, info: Info, args: Args) { self.push(info, AsyncPhase::Step, args); }
+
+    /// Ends the span, same as dropping it
+    pub fn end(self) {}
+
+    fn push(&self, info: Info, phase: AsyncPhase, args: Args)
+    {
+        if let Some(session) = &self.session
+        {
+            session.push(Trace(BaseInfo::build_now(info).with_args(args), AsyncEvent(phase, self.id)));
+        }
+    }
+}
+
+impl Drop for AsyncSpan
+{
+    fn drop(&mut self) { self.push(self.info, AsyncPhase::End, Args::new()); }
+}
@@ -0,0 +1,510 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use scoper_base::{AsyncId, AsyncPhase, InstantScopeSize, Value};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::{
+    Compression, Format, Trace, model::{Event, EventData, Text}, record_scope::MetaTrace, recording::Recording, sink::{RecordingInfo, TraceSink}, types::{FlowPhase, ObjectEvent}
+};
+
+const MAGIC: &[u8; 8] = b"SCOPERBT";
+const VERSION: u64 = 2;
+
+// Tags of the records, events are tagged from `EVENT` on by their kind
+const STRING: u8 = 0;
+const INFO: u8 = 1;
+const PROCESS_NAME: u8 = 2;
+const THREAD_NAME: u8 = 3;
+const EXTRAS: u8 = 4;
+const EVENT: u8 = 16;
+
+/// Writes the compact binary format while the events arrive, only the ids of the interned strings and infos are held
+/// A string or a distinct name, category, header and static args is written once as an entry of its table before it is used
+/// Event times are varint deltas to the previous event of the same thread
+///
+/// Layout, all integers are LEB128 varints:
+/// `magic version start record...`, every record is a tag followed by its fields
+pub struct BinarySink<W: Write>
+{
+    writer: W,
+    encoder: Encoder,
+    /// Records of the current batch
+    out: Vec<u8>,
+}
+
+impl<W: Write> BinarySink<W>
+{
+    pub fn new(writer: W) -> Self
+    {
+        Self {
+            writer,
+            encoder: Encoder::default(),
+            out: Vec::new(),
+        }
+    }
+
+    /// The writer, with the whole trace written to it if finished
+    pub fn into_inner(self) -> W { self.writer }
+
+    fn write_out(&mut self) -> io::Result<()>
+    {
+        self.writer.write_all(&self.out)?;
+        self.out.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> TraceSink for BinarySink<W>
+{
+    fn begin(&mut self, info: &RecordingInfo) -> io::Result<()>
+    {
+        self.out.extend_from_slice(MAGIC);
+        varint(&mut self.out, VERSION);
+        let start = info.start.duration_since(UNIX_EPOCH).unwrap_or_default();
+        varint(&mut self.out, start.as_nanos().try_into().unwrap_or(u64::MAX));
+        self.out.push(EXTRAS);
+        inline_string(&mut self.out, &JsonValue::Object(info.metadata.clone()).to_string());
+        self.write_out()
+    }
+
+    fn process_name(&mut self, header: &str, name: &str) -> io::Result<()>
+    {
+        let ids = [self.encoder.intern(&mut self.out, header), self.encoder.intern(&mut self.out, name)];
+        self.out.push(PROCESS_NAME);
+        for id in ids
+        {
+            varint(&mut self.out, id);
+        }
+        self.write_out()
+    }
+
+    fn thread_name(&mut self, header: &str, thread: u64, name: &str) -> io::Result<()>
+    {
+        let ids = [self.encoder.intern(&mut self.out, header), self.encoder.intern(&mut self.out, name)];
+        self.out.push(THREAD_NAME);
+        varint(&mut self.out, ids[0]);
+        varint(&mut self.out, thread);
+        varint(&mut self.out, ids[1]);
+        self.write_out()
+    }
+
+    fn events(&mut self, events: &[Event]) -> io::Result<()>
+    {
+        for event in events
+        {
+            self.encoder.event(&mut self.out, event);
+        }
+        self.write_out()
+    }
+
+    fn finish(&mut self) -> io::Result<()> { self.writer.flush() }
+}
+
+/// Writes `recording` in the compact binary format
+pub(crate) fn write(recording: &Recording, writer: impl Write + Send) -> io::Result<()>
+{
+    let mut sink = BinarySink::new(writer);
+    sink.begin(&RecordingInfo {
+        start: recording.start,
+        metadata: recording.extras.clone(),
+    })?;
+    for meta_trace in &recording.meta_traces
+    {
+        match meta_trace
+        {
+            MetaTrace::ProcessName(header, name) => sink.process_name(header, name)?,
+            MetaTrace::ThreadName(header, thread, name) => sink.thread_name(header, *thread, name)?,
+        }
+    }
+    for batch in recording.events.chunks(1024)
+    {
+        sink.events(batch)?;
+    }
+    sink.finish()
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn varint(out: &mut Vec<u8>, mut value: u64)
+{
+    while value >= 0x80
+    {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn inline_string(out: &mut Vec<u8>, string: &str)
+{
+    varint(out, string.len() as u64);
+    out.extend_from_slice(string.as_bytes());
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value)
+{
+    match value
+    {
+        Value::UInt(uint) =>
+        {
+            out.push(0);
+            varint(out, *uint);
+        },
+        Value::IInt(iint) =>
+        {
+            out.push(1);
+            // Zigzag keeps small negative numbers short
+            varint(out, ((iint << 1) ^ (iint >> 63)).cast_unsigned());
+        },
+        Value::Float(float) =>
+        {
+            out.push(2);
+            out.extend_from_slice(&float.to_le_bytes());
+        },
+        Value::Bool(bool) => out.extend([3, u8::from(*bool)]),
+        Value::Str(string) =>
+        {
+            out.push(4);
+            inline_string(out, string);
+        },
+    }
+}
+
+#[derive(Default)]
+struct Encoder
+{
+    string_ids: HashMap<String, u64>,
+    /// Name, category, header and static args
+    info_ids: HashMap<[u64; 4], u64>,
+    /// Time of the previous event of every thread
+    last_times: HashMap<u64, u64>,
+}
+
+impl Encoder
+{
+    /// Id of `string`, its table entry is written to `out` the first time
+    fn intern(&mut self, out: &mut Vec<u8>, string: &str) -> u64
+    {
+        if let Some(&id) = self.string_ids.get(string)
+        {
+            return id;
+        }
+        let id = self.string_ids.len() as u64;
+        self.string_ids.insert(string.to_string(), id);
+        out.push(STRING);
+        inline_string(out, string);
+        id
+    }
+
+    fn info(&mut self, out: &mut Vec<u8>, event: &Event) -> u64
+    {
+        let info = [&event.name, &event.category, &event.header, &event.static_args].map(|string| self.intern(out, string));
+        if let Some(&id) = self.info_ids.get(&info)
+        {
+            return id;
+        }
+        let id = self.info_ids.len() as u64;
+        self.info_ids.insert(info, id);
+        out.push(INFO);
+        for id in info
+        {
+            varint(out, id);
+        }
+        id
+    }
+
+    fn event(&mut self, out: &mut Vec<u8>, event: &Event)
+    {
+        // Table entries go before the record using them
+        let info = self.info(out, event);
+        let keys: Vec<_> = event.args.iter().map(|(key, _)| self.intern(out, key)).collect();
+        let tag = match &event.data
+        {
+            EventData::Scope { .. } => 0,
+            EventData::Counter(_) => 1,
+            EventData::Instant(InstantScopeSize::Thread) => 2,
+            EventData::Instant(InstantScopeSize::Process) => 3,
+            EventData::Instant(InstantScopeSize::Global) => 4,
+            EventData::Async(AsyncPhase::Begin, _) => 5,
+            EventData::Async(AsyncPhase::Step, _) => 6,
+            EventData::Async(AsyncPhase::End, _) => 7,
+            EventData::Flow(FlowPhase::Out, _) => 8,
+            EventData::Flow(FlowPhase::Step, _) => 9,
+            EventData::Flow(FlowPhase::In, _) => 10,
+            EventData::Object(ObjectEvent::Created(_)) => 11,
+            EventData::Object(ObjectEvent::Snapshot(..)) => 12,
+            EventData::Object(ObjectEvent::Destroyed(_)) => 13,
+        };
+        out.push(EVENT + tag);
+        varint(out, event.thread);
+        varint(out, info);
+        // The events arrive ordered by start
+        let last = self.last_times.insert(event.thread, event.time).unwrap_or_default();
+        varint(out, event.time.saturating_sub(last));
+        match &event.data
+        {
+            EventData::Scope { duration } => varint(out, *duration),
+            EventData::Counter(value) => write_value(out, value),
+            EventData::Instant(_) => (),
+            EventData::Async(_, id) =>
+            {
+                let (kind, id) = match *id
+                {
+                    AsyncId::Id(id) => (0, id),
+                    AsyncId::Local(id) => (1, id),
+                    AsyncId::Global(id) => (2, id),
+                };
+                out.push(kind);
+                varint(out, id);
+            },
+            EventData::Flow(_, id) | EventData::Object(ObjectEvent::Created(id) | ObjectEvent::Destroyed(id)) => varint(out, *id),
+            EventData::Object(ObjectEvent::Snapshot(id, snapshot)) =>
+            {
+                varint(out, *id);
+                inline_string(out, &snapshot.to_string());
+            },
+        }
+        varint(out, keys.len() as u64);
+        for (key, (_, value)) in keys.into_iter().zip(&event.args)
+        {
+            varint(out, key);
+            write_value(out, value);
+        }
+    }
+}
+
+/// Reads a binary trace written by [`BinarySink`]
+pub(crate) fn decode(data: &[u8]) -> io::Result<Recording>
+{
+    let mut decoder = Decoder { data, strings: Vec::new() };
+    if decoder.bytes(MAGIC.len())? != MAGIC
+    {
+        return Err(invalid("Not a binary scoper trace"));
+    }
+    let version = decoder.varint()?;
+    if version != VERSION
+    {
+        return Err(invalid(&format!("Unsupported binary trace version {version}")));
+    }
+    let start = UNIX_EPOCH + Duration::from_nanos(decoder.varint()?);
+
+    let mut infos = Vec::new();
+    let mut meta_traces = Vec::new();
+    let mut extras = Map::new();
+    let mut events = Vec::new();
+    let mut last_times = HashMap::new();
+    while !decoder.data.is_empty()
+    {
+        match decoder.byte()?
+        {
+            STRING =>
+            {
+                let string = decoder.inline_string()?;
+                decoder.strings.push(Text::Owned(string));
+            },
+            INFO => infos.push([decoder.string()?, decoder.string()?, decoder.string()?, decoder.string()?]),
+            PROCESS_NAME => meta_traces.push(MetaTrace::ProcessName(decoder.string()?, decoder.string()?.into_owned())),
+            THREAD_NAME => meta_traces.push(MetaTrace::ThreadName(decoder.string()?, decoder.varint()?, decoder.string()?.into_owned())),
+            EXTRAS =>
+            {
+                if let JsonValue::Object(map) = serde_json::from_str(&decoder.inline_string()?)?
+                {
+                    extras.extend(map);
+                }
+            },
+            tag if tag >= EVENT =>
+            {
+                let thread = decoder.varint()?;
+                let [name, category, header, static_args] = infos
+                    .get(decoder.length()?)
+                    .cloned()
+                    .ok_or_else(|| invalid("Unknown info"))?;
+                let time: &mut u64 = last_times.entry(thread).or_default();
+                *time += decoder.varint()?;
+                let time = *time;
+                let data = decoder.data(tag - EVENT)?;
+                let mut args = Vec::new();
+                for _ in 0..decoder.varint()?
+                {
+                    args.push((decoder.string()?, decoder.value()?));
+                }
+                events.push(Event {
+                    name,
+                    category,
+                    header,
+                    static_args,
+                    args,
+                    thread,
+                    time,
+                    data,
+                });
+            },
+            _ => return Err(invalid("Unknown record")),
+        }
+    }
+    Event::sort(&mut events);
+
+    Ok(Recording {
+        events,
+        meta_traces,
+        extras,
+        start,
+    })
+}
+
+fn invalid(message: &str) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, message.to_string()) }
+
+struct Decoder<'a>
+{
+    data: &'a [u8],
+    strings: Vec<Text>,
+}
+
+impl<'a> Decoder<'a>
+{
+    fn bytes(&mut self, length: usize) -> io::Result<&'a [u8]>
+    {
+        if self.data.len() < length
+        {
+            return Err(invalid("Truncated binary trace"));
+        }
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> { Ok(self.bytes(1)?[0]) }
+
+    fn varint(&mut self) -> io::Result<u64>
+    {
+        let mut value = 0;
+        for shift in (0..64).step_by(7)
+        {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0
+            {
+                return Ok(value);
+            }
+        }
+        Err(invalid("Varint too long"))
+    }
+
+    fn length(&mut self) -> io::Result<usize> { usize::try_from(self.varint()?).map_err(|_| invalid("Length too big")) }
+
+    fn string(&mut self) -> io::Result<Text>
+    {
+        let id = self.length()?;
+        self.strings.get(id).cloned().ok_or_else(|| invalid("Unknown string"))
+    }
+
+    fn inline_string(&mut self) -> io::Result<String>
+    {
+        let length = self.length()?;
+        let bytes = self.bytes(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("Invalid utf-8 in string"))
+    }
+
+    fn data(&mut self, tag: u8) -> io::Result<EventData>
+    {
+        Ok(match tag
+        {
+            0 => EventData::Scope { duration: self.varint()? },
+            1 => EventData::Counter(self.value()?),
+            2 => EventData::Instant(InstantScopeSize::Thread),
+            3 => EventData::Instant(InstantScopeSize::Process),
+            4 => EventData::Instant(InstantScopeSize::Global),
+            5..=7 =>
+            {
+                let phase = [AsyncPhase::Begin, AsyncPhase::Step, AsyncPhase::End][usize::from(tag - 5)];
+                let id = match (self.byte()?, self.varint()?)
+                {
+                    (0, id) => AsyncId::Id(id),
+                    (1, id) => AsyncId::Local(id),
+                    (2, id) => AsyncId::Global(id),
+                    _ => return Err(invalid("Unknown async id")),
+                };
+                EventData::Async(phase, id)
+            },
+            8 => EventData::Flow(FlowPhase::Out, self.varint()?),
+            9 => EventData::Flow(FlowPhase::Step, self.varint()?),
+            10 => EventData::Flow(FlowPhase::In, self.varint()?),
+            11 => EventData::Object(ObjectEvent::Created(self.varint()?)),
+            12 =>
+            {
+                let id = self.varint()?;
+                EventData::Object(ObjectEvent::Snapshot(id, serde_json::from_str(&self.inline_string()?)?))
+            },
+            13 => EventData::Object(ObjectEvent::Destroyed(self.varint()?)),
+            _ => return Err(invalid("Unknown event")),
+        })
+    }
+
+    fn value(&mut self) -> io::Result<Value>
+    {
+        Ok(match self.byte()?
+        {
+            0 => Value::UInt(self.varint()?),
+            1 =>
+            {
+                let zigzag = self.varint()?;
+                Value::IInt((zigzag >> 1).cast_signed() ^ -(zigzag & 1).cast_signed())
+            },
+            2 => Value::Float(f64::from_le_bytes(self.bytes(8)?.try_into().expect("Took 8 bytes"))),
+            3 => Value::Bool(self.byte()? != 0),
+            4 => Value::Str(self.inline_string()?),
+            _ => return Err(invalid("Unknown value")),
+        })
+    }
+}
+
+/// A recording read back from the compact binary format, written with a `.scoper` extension
+/// Converts it to any of the other formats after the fact, [`Trace`] reads json traces as well
+pub struct BinaryTrace(Recording);
+
+impl BinaryTrace
+{
+    /// Reads the binary trace at `path`, `.gz` or `.zst` compressed ones are decompressed
+    ///
+    /// # Errors
+    /// Returns an Error if the file can not be read or is no binary trace
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self>
+    {
+        let mut data = Vec::new();
+        Compression::from_path(path.as_ref()).open(path.as_ref())?.read_to_end(&mut data)?;
+        Ok(Self(decode(&data)?))
+    }
+
+    /// Number of recorded events
+    #[must_use]
+    pub fn event_count(&self) -> usize { self.0.events.len() }
+
+    /// Writes the recording to `path` in the format and compression picked by its extension
+    ///
+    /// # Errors
+    /// Returns an Error if the file can not be written
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()>
+    {
+        let format = Format::from_path(path.as_ref());
+        let path = format.output_path(path.as_ref());
+        self.0.write(&path, format, Compression::from_path(&path))
+    }
+
+    /// Writes the recording to `path` as it is, in the given format and compression
+    ///
+    /// # Errors
+    /// Returns an Error if the file can not be written
+    pub fn write_as(&self, path: impl AsRef<Path>, format: Format, compression: Compression) -> io::Result<()>
+    {
+        self.0.write(path.as_ref(), format, compression)
+    }
+}
+
+impl From<BinaryTrace> for Trace
+{
+    fn from(BinaryTrace(recording): BinaryTrace) -> Self { Self(recording) }
+}
@@ -0,0 +1,105 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+use crate::{model::Event, nesting};
+
+/// Function of the call graph, scopes are equal if name and category are
+type Function<'a> = (&'a str, &'a str);
+
+#[derive(Default)]
+struct Costs<'a>
+{
+    /// Nanoseconds spent in the function itself
+    self_time: u64,
+    /// Number of calls and inclusive nanoseconds of every callee
+    calls: BTreeMap<Function<'a>, (u64, u64)>,
+}
+
+/// Callgrind profile of the scopes in `events` with costs in microseconds, for `KCachegrind`
+/// The category of a scope is used as its object and file, the call graph of all threads is merged
+pub(crate) fn file(events: &[Event]) -> String
+{
+    let mut functions: BTreeMap<Function, Costs> = BTreeMap::new();
+    for thread in nesting::threads(events)
+    {
+        for span in &thread.spans
+        {
+            let function = (&*span.event.name, &*span.event.category);
+            functions.entry(function).or_default().self_time += span.self_time();
+            if let Some(parent) = span.parent
+            {
+                let parent = thread.spans[parent].event;
+                let call = functions
+                    .entry((&parent.name, &parent.category))
+                    .or_default()
+                    .calls
+                    .entry(function)
+                    .or_default();
+                call.0 += 1;
+                call.1 += span.duration();
+            }
+        }
+    }
+
+    let total: u64 = functions.values().map(|costs| costs.self_time).sum();
+    let mut file = String::new();
+    let _ = write!(file, "# callgrind format\nversion: 1\ncreator: scoper\npositions: line\nevents: us\nsummary: {}\n", micros(total));
+    let mut names = Names::default();
+    for ((name, category), costs) in &functions
+    {
+        let _ = write!(
+            file,
+            "\nob={}\nfl={}\nfn={}\n0 {}\n",
+            names.object(category),
+            names.file(category),
+            names.function(name),
+            micros(costs.self_time)
+        );
+        for ((callee, callee_category), (count, inclusive)) in &costs.calls
+        {
+            if callee_category != category
+            {
+                let _ = write!(file, "cob={}\ncfi={}\n", names.object(callee_category), names.file(callee_category));
+            }
+            let _ = write!(file, "cfn={}\ncalls={count} 0\n0 {}\n", names.function(callee), micros(*inclusive));
+        }
+    }
+    file
+}
+
+fn micros(nanos: u64) -> u64 { nanos / 1000 }
+
+/// Name compression, the first use of a name defines its id and later ones only repeat the id
+#[derive(Default)]
+struct Names
+{
+    objects: HashMap<String, usize>,
+    files: HashMap<String, usize>,
+    functions: HashMap<String, usize>,
+}
+
+impl Names
+{
+    fn object(&mut self, category: &str) -> String { Self::compress(&mut self.objects, &Self::module(category)) }
+
+    fn file(&mut self, category: &str) -> String { Self::compress(&mut self.files, &Self::module(category)) }
+
+    fn function(&mut self, name: &str) -> String { Self::compress(&mut self.functions, name) }
+
+    /// The macros separate the module path by commas to split it into trace categories
+    /// Scopes without category are in an unknown module
+    fn module(category: &str) -> String { if category.is_empty() { "???".to_string() } else { category.replace(',', "::") } }
+
+    fn compress(ids: &mut HashMap<String, usize>, name: &str) -> String
+    {
+        if let Some(id) = ids.get(name)
+        {
+            return format!("({id})");
+        }
+        let id = ids.len() + 1;
+        ids.insert(name.to_string(), id);
+        format!("({id}) {}", name.replace('\n', " "))
+    }
+}
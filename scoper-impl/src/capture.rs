@@ -0,0 +1,202 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use scoper_base::Value;
+
+use crate::{
+    RecordScope, model::{Event, EventData}, nesting, sink::TraceSink
+};
+
+/// Records everything `f` does on the current thread and returns it instead of writing a file
+/// Threads spawned inside join with [`SessionHandle::current`](crate::SessionHandle::current)
+pub fn capture(f: impl FnOnce()) -> CapturedTrace
+{
+    let captured = Captured::default();
+    let record = RecordScope::with_sink(captured.clone());
+    f();
+    drop(record);
+    CapturedTrace::new(captured.take())
+}
+
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<Event>>>);
+
+impl Captured
+{
+    fn take(&self) -> Vec<Event> { std::mem::take(&mut *self.0.lock().expect("Could not get access")) }
+}
+
+impl TraceSink for Captured
+{
+    fn events(&mut self, events: &[Event]) -> io::Result<()>
+    {
+        self.0.lock().expect("Could not get access").extend_from_slice(events);
+        Ok(())
+    }
+}
+
+/// Events recorded by [`capture`] with the call trees of their scopes
+pub struct CapturedTrace
+{
+    events: Vec<Event>,
+    scopes: Vec<Node>,
+}
+
+/// Scope in the call tree of its thread
+struct Node
+{
+    event: usize,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// Clamped to the end of the parent
+    end: u64,
+    self_time: u64,
+}
+
+impl CapturedTrace
+{
+    fn new(events: Vec<Event>) -> Self
+    {
+        let mut scopes: Vec<Node> = Vec::new();
+        // Attribute scopes have no header, they still nest inside the headered scopes of their thread
+        for thread in nesting::threads_across_headers(&events)
+        {
+            let offset = scopes.len();
+            for span in &thread.spans
+            {
+                let parent = span.parent.map(|parent| parent + offset);
+                let index = scopes.len();
+                if let Some(parent) = parent
+                {
+                    scopes[parent].children.push(index);
+                }
+                scopes.push(Node {
+                    event: span.index,
+                    parent,
+                    children: Vec::new(),
+                    end: span.end,
+                    self_time: span.self_time(),
+                });
+            }
+        }
+        Self { events, scopes }
+    }
+
+    /// All events in order of their start
+    #[must_use]
+    pub fn events(&self) -> &[Event] { &self.events }
+
+    /// All scopes, grouped by thread and in order of their start
+    pub fn scopes(&self) -> impl Iterator<Item = CapturedScope<'_>> { (0..self.scopes.len()).map(|index| self.scope(index)) }
+
+    /// Scopes without a parent
+    pub fn roots(&self) -> impl Iterator<Item = CapturedScope<'_>> { self.scopes().filter(|scope| scope.node().parent.is_none()) }
+
+    /// Every scope called `name`
+    pub fn find_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = CapturedScope<'a>> { self.scopes().filter(move |scope| scope.name() == name) }
+
+    /// First scope called `name`
+    #[must_use]
+    pub fn find(&self, name: &str) -> Option<CapturedScope<'_>> { self.scopes().find(|scope| scope.name() == name) }
+
+    /// Number of events of any kind called `name`
+    #[must_use]
+    pub fn count(&self, name: &str) -> usize { self.events.iter().filter(|event| event.name == name).count() }
+
+    /// Summed duration of the scopes called `name`, nested calls are counted again
+    #[must_use]
+    pub fn total_time(&self, name: &str) -> Duration { self.find_all(name).map(|scope| scope.duration()).sum() }
+
+    /// Values of the counters called `name` in order
+    pub fn counters<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Value>
+    {
+        self.events.iter().filter(move |event| event.name == name).filter_map(|event| match &event.data
+        {
+            EventData::Counter(value) => Some(value),
+            _ => None,
+        })
+    }
+
+    /// Instants called `name` in order
+    pub fn instants<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Event>
+    {
+        self.events
+            .iter()
+            .filter(move |event| event.name == name && matches!(event.data, EventData::Instant(_)))
+    }
+
+    fn scope(&self, index: usize) -> CapturedScope<'_> { CapturedScope { trace: self, index } }
+}
+
+/// A scope of a [`CapturedTrace`] that knows its place in the call tree
+#[derive(Clone, Copy)]
+pub struct CapturedScope<'a>
+{
+    trace: &'a CapturedTrace,
+    index: usize,
+}
+
+impl<'a> CapturedScope<'a>
+{
+    fn node(&self) -> &'a Node { &self.trace.scopes[self.index] }
+
+    #[must_use]
+    pub fn event(&self) -> &'a Event { &self.trace.events[self.node().event] }
+
+    #[must_use]
+    pub fn name(&self) -> &'a str { &self.event().name }
+
+    /// Printed id of the recording thread
+    #[must_use]
+    pub fn thread(&self) -> u64 { self.event().thread }
+
+    /// Since the start of the capture
+    #[must_use]
+    pub fn start(&self) -> Duration { Duration::from_nanos(self.event().time) }
+
+    /// Clamped to the parent, scopes recorded by hand might overlap it
+    #[must_use]
+    pub fn duration(&self) -> Duration { Duration::from_nanos(self.node().end - self.event().time) }
+
+    /// Time not spent in any of the children
+    #[must_use]
+    pub fn self_time(&self) -> Duration { Duration::from_nanos(self.node().self_time) }
+
+    /// Runtime argument `key`
+    #[must_use]
+    pub fn arg(&self, key: &str) -> Option<&'a Value> { self.event().args.iter().find(|(name, _)| name == key).map(|(_, value)| value) }
+
+    #[must_use]
+    pub fn parent(&self) -> Option<Self> { self.node().parent.map(|parent| self.trace.scope(parent)) }
+
+    /// Direct children in order of their start
+    pub fn children(&self) -> impl Iterator<Item = CapturedScope<'a>> + use<'a>
+    {
+        let trace = self.trace;
+        self.node().children.iter().map(move |&child| trace.scope(child))
+    }
+
+    /// First direct child called `name`
+    #[must_use]
+    pub fn child(&self, name: &str) -> Option<Self> { self.children().find(|child| child.name() == name) }
+
+    /// Whether a scope called `name` encloses this one
+    #[must_use]
+    pub fn is_inside(&self, name: &str) -> bool { std::iter::successors(self.parent(), CapturedScope::parent).any(|scope| scope.name() == name) }
+}
+
+impl std::fmt::Debug for CapturedScope<'_>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        f.debug_struct("CapturedScope")
+            .field("name", &self.name())
+            .field("thread", &self.thread())
+            .field("start", &self.start())
+            .field("duration", &self.duration())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,408 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use scoper_base::{AsyncId, AsyncPhase, InstantScopeSize, Value};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::{
+    Compression, model::{Event, EventData, Text}, record_scope::MetaTrace, recording::Recording, types::{FlowPhase, ObjectEvent}
+};
+
+/// A chrome trace like the json output writes it, or any other tool following the trace event format
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChromeTrace
+{
+    #[serde(rename = "traceEvents")]
+    pub events: Vec<ChromeEvent>,
+    #[serde(rename = "displayTimeUnit", default, skip_serializing_if = "Option::is_none")]
+    pub display_time_unit: Option<String>,
+    /// All other fields, like the metadata and `droppedEvents`
+    #[serde(flatten)]
+    pub metadata: Map<String, JsonValue>,
+}
+
+/// One entry of `traceEvents`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChromeEvent
+{
+    #[serde(default)]
+    pub name: String,
+    /// Categories separated by commas
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub cat: String,
+    pub ph: Phase,
+    /// The header for recordings of scoper
+    #[serde(default)]
+    pub pid: ChromeId,
+    #[serde(default)]
+    pub tid: ChromeId,
+    /// Microseconds
+    #[serde(default)]
+    pub ts: f64,
+    /// Microseconds, only for [`Phase::Complete`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dur: Option<f64>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub args: Map<String, JsonValue>,
+    /// Fields of some phases like `id`, `id2`, `s` and `bp`
+    #[serde(flatten)]
+    pub extra: Map<String, JsonValue>,
+}
+
+/// Process or thread id, some tools use names instead of numbers
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ChromeId
+{
+    Number(u64),
+    Name(String),
+}
+
+impl Default for ChromeId
+{
+    fn default() -> Self { Self::Number(0) }
+}
+
+impl fmt::Display for ChromeId
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            Self::Number(number) => write!(f, "{number}"),
+            Self::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+/// The `ph` of an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase
+{
+    Begin,
+    End,
+    Complete,
+    /// The deprecated `I` is read as `i` as well
+    Instant,
+    Counter,
+    AsyncStart,
+    AsyncStep,
+    AsyncEnd,
+    FlowStart,
+    FlowStep,
+    FlowEnd,
+    ObjectCreated,
+    ObjectSnapshot,
+    ObjectDestroyed,
+    Metadata,
+    Sample,
+    Mark,
+    ClockSync,
+    Other(char),
+}
+
+impl Phase
+{
+    #[must_use]
+    pub const fn code(self) -> char
+    {
+        match self
+        {
+            Self::Begin => 'B',
+            Self::End => 'E',
+            Self::Complete => 'X',
+            Self::Instant => 'i',
+            Self::Counter => 'C',
+            Self::AsyncStart => 'b',
+            Self::AsyncStep => 'n',
+            Self::AsyncEnd => 'e',
+            Self::FlowStart => 's',
+            Self::FlowStep => 't',
+            Self::FlowEnd => 'f',
+            Self::ObjectCreated => 'N',
+            Self::ObjectSnapshot => 'O',
+            Self::ObjectDestroyed => 'D',
+            Self::Metadata => 'M',
+            Self::Sample => 'P',
+            Self::Mark => 'R',
+            Self::ClockSync => 'c',
+            Self::Other(code) => code,
+        }
+    }
+
+    #[must_use]
+    pub const fn from_code(code: char) -> Self
+    {
+        match code
+        {
+            'B' => Self::Begin,
+            'E' => Self::End,
+            'X' => Self::Complete,
+            'i' | 'I' => Self::Instant,
+            'C' => Self::Counter,
+            'b' => Self::AsyncStart,
+            'n' => Self::AsyncStep,
+            'e' => Self::AsyncEnd,
+            's' => Self::FlowStart,
+            't' => Self::FlowStep,
+            'f' => Self::FlowEnd,
+            'N' => Self::ObjectCreated,
+            'O' => Self::ObjectSnapshot,
+            'D' => Self::ObjectDestroyed,
+            'M' => Self::Metadata,
+            'P' => Self::Sample,
+            'R' => Self::Mark,
+            'c' => Self::ClockSync,
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl Serialize for Phase
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> { serializer.serialize_char(self.code()) }
+}
+
+impl<'de> Deserialize<'de> for Phase
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> { char::deserialize(deserializer).map(Self::from_code) }
+}
+
+impl ChromeTrace
+{
+    /// Loads the trace at `path`, a `.gz` or `.zst` extension is decompressed
+    ///
+    /// # Errors
+    /// Returns an Error if the file can not be read or is no chrome trace
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> { Self::from_reader(Compression::from_path(path.as_ref()).open(path.as_ref())?) }
+
+    /// Reads the object form with `traceEvents` or the bare array form
+    ///
+    /// # Errors
+    /// Returns an Error if reading fails or it is no chrome trace
+    pub fn from_reader(mut reader: impl Read) -> io::Result<Self>
+    {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        Ok(text.parse()?)
+    }
+
+    /// Writes the object form
+    ///
+    /// # Errors
+    /// Returns an Error if writing fails
+    pub fn write(&self, writer: impl Write) -> io::Result<()> { Ok(serde_json::to_writer(writer, self)?) }
+
+    /// Name of the process `pid` given by a metadata event
+    #[must_use]
+    pub fn process_name(&self, pid: &ChromeId) -> Option<&str> { self.metadata_name("process_name", pid, None) }
+
+    /// Name of the thread `tid` of `pid` given by a metadata event
+    #[must_use]
+    pub fn thread_name(&self, pid: &ChromeId, tid: &ChromeId) -> Option<&str> { self.metadata_name("thread_name", pid, Some(tid)) }
+
+    fn metadata_name(&self, kind: &str, pid: &ChromeId, tid: Option<&ChromeId>) -> Option<&str>
+    {
+        self.events
+            .iter()
+            .rev()
+            .filter(|event| event.ph == Phase::Metadata && event.name == kind && &event.pid == pid && tid.is_none_or(|tid| &event.tid == tid))
+            .find_map(|event| event.args.get("name")?.as_str())
+    }
+}
+
+impl FromStr for ChromeTrace
+{
+    type Err = serde_json::Error;
+
+    /// The array form may miss its closing bracket, like traces of a crashed program
+    fn from_str(text: &str) -> Result<Self, Self::Err>
+    {
+        let text = text.trim();
+        if !text.starts_with('[')
+        {
+            return serde_json::from_str(text);
+        }
+        let events = match serde_json::from_str(text)
+        {
+            Err(err) if err.is_eof() => serde_json::from_str(&format!("{}]", text.trim_end_matches(','))),
+            events => events,
+        }?;
+        Ok(Self {
+            events,
+            ..Self::default()
+        })
+    }
+}
+
+impl ChromeTrace
+{
+    /// Typed events and names of the trace, in order
+    /// Begin and end pairs become scopes, the first arg of counters written by other tools their value
+    pub(crate) fn into_recording(self) -> Recording
+    {
+        let mut threads = Threads::default();
+        let mut meta_traces = Vec::new();
+        let mut events = Vec::with_capacity(self.events.len());
+        // Begin events waiting for their end, per header and thread
+        let mut open: HashMap<(String, u64), Vec<Event>> = HashMap::new();
+        for mut chrome in self.events
+        {
+            let header = chrome.pid.to_string();
+            let thread = threads.id(&chrome.tid);
+            if chrome.ph == Phase::Metadata
+            {
+                let name = chrome.args.get("name").and_then(JsonValue::as_str).unwrap_or_default().to_string();
+                match chrome.name.as_str()
+                {
+                    "process_name" => meta_traces.push(MetaTrace::ProcessName(header.into(), name)),
+                    "thread_name" => meta_traces.push(MetaTrace::ThreadName(header.into(), thread, name)),
+                    _ => (),
+                }
+                continue;
+            }
+            let time = nanos(chrome.ts);
+            let data = match chrome.ph
+            {
+                Phase::Complete => EventData::Scope { duration: nanos(chrome.dur.unwrap_or_default()) },
+                Phase::Begin => EventData::Scope { duration: 0 },
+                Phase::End =>
+                {
+                    if let Some(mut begin) = open.get_mut(&(header, thread)).and_then(Vec::pop)
+                    {
+                        begin.data = EventData::Scope { duration: time.saturating_sub(begin.time) };
+                        events.push(begin);
+                    }
+                    continue;
+                },
+                Phase::Instant => EventData::Instant(match chrome.extra.get("s").and_then(JsonValue::as_str)
+                {
+                    Some("g") => InstantScopeSize::Global,
+                    Some("p") => InstantScopeSize::Process,
+                    _ => InstantScopeSize::Thread,
+                }),
+                Phase::Counter =>
+                {
+                    let counter = chrome.args.remove("").or_else(|| chrome.args.keys().next().cloned().and_then(|key| chrome.args.remove(&key)));
+                    EventData::Counter(counter.as_ref().map_or(Value::UInt(0), value))
+                },
+                Phase::AsyncStart | Phase::AsyncStep | Phase::AsyncEnd =>
+                {
+                    let phase = match chrome.ph
+                    {
+                        Phase::AsyncStart => AsyncPhase::Begin,
+                        Phase::AsyncStep => AsyncPhase::Step,
+                        _ => AsyncPhase::End,
+                    };
+                    let id = match chrome.extra.get("id2")
+                    {
+                        Some(JsonValue::Object(id2)) if id2.contains_key("global") => AsyncId::Global(id(id2.get("global"))),
+                        Some(JsonValue::Object(id2)) => AsyncId::Local(id(id2.get("local"))),
+                        _ => AsyncId::Id(id(chrome.extra.get("id"))),
+                    };
+                    EventData::Async(phase, id)
+                },
+                Phase::FlowStart => EventData::Flow(FlowPhase::Out, id(chrome.extra.get("id"))),
+                Phase::FlowStep => EventData::Flow(FlowPhase::Step, id(chrome.extra.get("id"))),
+                Phase::FlowEnd => EventData::Flow(FlowPhase::In, id(chrome.extra.get("id"))),
+                Phase::ObjectCreated => EventData::Object(ObjectEvent::Created(id(chrome.extra.get("id")))),
+                Phase::ObjectSnapshot =>
+                {
+                    let snapshot = chrome.args.remove("snapshot").unwrap_or_default();
+                    EventData::Object(ObjectEvent::Snapshot(id(chrome.extra.get("id")), snapshot))
+                },
+                Phase::ObjectDestroyed => EventData::Object(ObjectEvent::Destroyed(id(chrome.extra.get("id")))),
+                // Nothing scoper records
+                Phase::Metadata | Phase::Sample | Phase::Mark | Phase::ClockSync | Phase::Other(_) => continue,
+            };
+
+            // Scalars become runtime args, everything else stays json in the static args
+            let (scalars, objects): (Vec<_>, Vec<_>) = chrome.args.into_iter().partition(|(_, value)| !value.is_object() && !value.is_array());
+            let event = Event {
+                name: chrome.name.into(),
+                category: chrome.cat.into(),
+                header: header.clone().into(),
+                static_args: if objects.is_empty() { Text::Borrowed("") } else { JsonValue::Object(objects.into_iter().collect()).to_string().into() },
+                args: scalars.into_iter().map(|(key, json)| (key.into(), value(&json))).collect(),
+                thread,
+                time,
+                data,
+            };
+            if chrome.ph == Phase::Begin
+            {
+                open.entry((header, thread)).or_default().push(event);
+            }
+            else
+            {
+                events.push(event);
+            }
+        }
+        Event::sort(&mut events);
+
+        let mut recording = Recording::empty();
+        recording.events = events;
+        recording.meta_traces = meta_traces;
+        recording.extras = self.metadata;
+        recording
+    }
+}
+
+/// Thread ids of the trace, names get ids of their own
+#[derive(Default)]
+struct Threads(HashMap<String, u64>);
+
+impl Threads
+{
+    fn id(&mut self, tid: &ChromeId) -> u64
+    {
+        match tid
+        {
+            ChromeId::Number(number) => *number,
+            ChromeId::Name(name) =>
+            {
+                // Above the 32 bit ids of the operating systems
+                let next = (1 << 32) + self.0.len() as u64;
+                *self.0.entry(name.clone()).or_insert(next)
+            },
+        }
+    }
+}
+
+/// Microseconds of the trace as nanoseconds
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn nanos(micros: f64) -> u64 { (micros * 1000.0).round().max(0.0) as u64 }
+
+/// Ids are written as hex strings, some tools use numbers
+fn id(id: Option<&JsonValue>) -> u64
+{
+    match id
+    {
+        Some(JsonValue::Number(number)) => number.as_u64().unwrap_or_default(),
+        Some(JsonValue::String(string)) =>
+        {
+            string.strip_prefix("0x").map_or_else(|| string.parse().ok(), |hex| u64::from_str_radix(hex, 16).ok()).unwrap_or_default()
+        },
+        _ => 0,
+    }
+}
+
+fn value(json: &JsonValue) -> Value
+{
+    match json
+    {
+        JsonValue::Bool(bool) => Value::Bool(*bool),
+        JsonValue::Number(number) => number
+            .as_u64()
+            .map(Value::UInt)
+            .or_else(|| number.as_i64().map(Value::IInt))
+            .unwrap_or_else(|| Value::Float(number.as_f64().unwrap_or_default())),
+        JsonValue::String(string) => Value::Str(string.clone()),
+        other => Value::Str(other.to_string()),
+    }
+}
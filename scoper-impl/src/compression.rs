@@ -0,0 +1,122 @@
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder};
+
+/// Compression of a written file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression
+{
+    #[default]
+    None,
+    /// Loads directly in `about://tracing` and `ui.perfetto.dev`
+    Gzip,
+    Zstd,
+}
+
+impl Compression
+{
+    /// Compression picked by the extension of `path`, `.gz` or `.zst`
+    #[must_use]
+    pub fn from_path(path: &Path) -> Self
+    {
+        match path.extension().and_then(|extension| extension.to_str())
+        {
+            Some("gz") => Self::Gzip,
+            Some("zst") => Self::Zstd,
+            _ => Self::None,
+        }
+    }
+
+    /// `path` without the extension of this compression, e.g. `trace.json` of `trace.json.gz`
+    pub(crate) fn strip(self, path: &Path) -> PathBuf
+    {
+        if self != Self::None && Self::from_path(path) == self { path.with_extension("") } else { path.to_path_buf() }
+    }
+
+    /// `path` with the extension of this compression appended
+    pub(crate) fn append(self, path: PathBuf) -> PathBuf
+    {
+        let extension = match self
+        {
+            Self::None => return path,
+            Self::Gzip => ".gz",
+            Self::Zstd => ".zst",
+        };
+        let mut path = OsString::from(path);
+        path.push(extension);
+        path.into()
+    }
+
+    /// Creates the file at `path`, everything written to it is compressed
+    pub(crate) fn create(self, path: &Path) -> io::Result<Encoder>
+    {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match self
+        {
+            Self::None => Encoder::Plain(file),
+            Self::Gzip => Encoder::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+            Self::Zstd => Encoder::Zstd(zstd::Encoder::new(file, 0)?),
+        })
+    }
+
+    /// Opens the file at `path` and decompresses it while reading
+    pub(crate) fn open(self, path: &Path) -> io::Result<Box<dyn Read>>
+    {
+        let file = BufReader::new(File::open(path)?);
+        Ok(match self
+        {
+            Self::None => Box::new(file),
+            Self::Gzip => Box::new(GzDecoder::new(file)),
+            Self::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+        })
+    }
+}
+
+/// Writer of a compressed file, [`Encoder::finish`] writes the end of the stream
+pub(crate) enum Encoder
+{
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Encoder
+{
+    pub(crate) fn finish(self) -> io::Result<()>
+    {
+        match self
+        {
+            Self::Plain(mut file) => file.flush(),
+            Self::Gzip(encoder) => encoder.finish()?.flush(),
+            Self::Zstd(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Encoder
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        match self
+        {
+            Self::Plain(file) => file.write(buf),
+            Self::Gzip(encoder) => encoder.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        match self
+        {
+            Self::Plain(file) => file.flush(),
+            Self::Gzip(encoder) => encoder.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
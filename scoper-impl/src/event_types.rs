@@ -8,17 +8,17 @@ pub(crate) enum EventType
     //End,
     Instant,
 
-    //AsyncStart,
-    //AsyncProgress,
-    //AsyncFinish,
+    AsyncStart,
+    AsyncProgress,
+    AsyncFinish,
 
-    //FlowStart,
-    //FlowProgress,
-    //FlowFinish,
+    FlowStart,
+    FlowProgress,
+    FlowFinish,
 
-    //ObjectCreated,
-    //ObjectSnapshot,
-    //ObjectDestroyed,
+    ObjectCreated,
+    ObjectSnapshot,
+    ObjectDestroyed,
     Counter,
     //Sample, //deprecated
     //ClockSync,
@@ -43,17 +43,17 @@ impl EventType
             //End => 'E',
             Instant => 'i',
 
-            //AsyncStart => 'b',
-            //AsyncProgress => 'n',
-            //AsyncFinish => 'e',
+            AsyncStart => 'b',
+            AsyncProgress => 'n',
+            AsyncFinish => 'e',
 
-            //FlowStart => 's',
-            //FlowProgress => 't',
-            //FlowFinish => 'f',
+            FlowStart => 's',
+            FlowProgress => 't',
+            FlowFinish => 'f',
 
-            //ObjectCreated => 'N',
-            //ObjectSnapshot => 'O',
-            //ObjectDestroyed => 'D',
+            ObjectCreated => 'N',
+            ObjectSnapshot => 'O',
+            ObjectDestroyed => 'D',
             Counter => 'C',
             //in source Sample => P
             //ClockSync => 'c',
@@ -0,0 +1,279 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    time::SystemTime,
+};
+
+use fxprof_processed_profile::{
+    CategoryColor, CategoryHandle, CounterHandle, Marker, MarkerFieldFlags, MarkerFieldFormat, MarkerLocations, MarkerTiming,
+    MarkerTypeHandle, ProcessHandle, Profile, ReferenceTimestamp, RuntimeSchemaMarkerField, RuntimeSchemaMarkerSchema,
+    SamplingInterval, StringHandle, ThreadHandle, Timestamp,
+};
+use scoper_base::{AsyncId, AsyncPhase, Value};
+
+use crate::{
+    model::{Event, EventData, Text}, record_scope::MetaTrace, types::{FlowPhase, ObjectEvent}
+};
+
+/// Builds a Firefox Profiler processed profile of `events`
+/// Headers become processes, scopes interval markers and every other event an instant marker
+/// Async spans and objects are paired into interval markers on the thread they began on
+pub(crate) fn profile(events: &[Event], meta_traces: &[MetaTrace], start: SystemTime) -> Profile
+{
+    let mut writer = Writer::new(meta_traces, start);
+    for event in events
+    {
+        writer.add(event);
+    }
+    writer.finish()
+}
+
+#[derive(Hash, PartialEq, Eq)]
+enum SpanKey
+{
+    Async(Text, u64),
+    Object(u64),
+}
+
+struct Writer<'a>
+{
+    meta_traces: &'a [MetaTrace],
+    profile: Profile,
+    marker_type: MarkerTypeHandle,
+    processes: HashMap<Text, ProcessHandle>,
+    threads: HashMap<(Text, u64), ThreadHandle>,
+    categories: HashMap<Text, CategoryHandle>,
+    counters: HashMap<(Text, Text), (CounterHandle, f64)>,
+    /// Async spans and objects that did not end yet
+    open: HashMap<SpanKey, (ThreadHandle, u64, ScoperMarker)>,
+}
+
+impl<'a> Writer<'a>
+{
+    fn new(meta_traces: &'a [MetaTrace], start: SystemTime) -> Self
+    {
+        let mut profile = Profile::new("scoper", ReferenceTimestamp::from_system_time(start), SamplingInterval::from_millis(1));
+        let marker_type = profile.register_marker_type(RuntimeSchemaMarkerSchema {
+            type_name: "scoper".to_string(),
+            description: Some("Recorded with scoper".to_string()),
+            locations: MarkerLocations::MARKER_CHART | MarkerLocations::MARKER_TABLE | MarkerLocations::TIMELINE_OVERVIEW,
+            chart_label: Some("{marker.name}".to_string()),
+            tooltip_label: None,
+            table_label: Some("{marker.name} {marker.data.args}".to_string()),
+            fields: vec![RuntimeSchemaMarkerField {
+                key: "args".to_string(),
+                label: "Arguments".to_string(),
+                format: MarkerFieldFormat::String,
+                flags: MarkerFieldFlags::SEARCHABLE,
+            }],
+            graphs: Vec::new(),
+        });
+        Self {
+            meta_traces,
+            profile,
+            marker_type,
+            processes: HashMap::new(),
+            threads: HashMap::new(),
+            categories: HashMap::new(),
+            counters: HashMap::new(),
+            open: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, event: &Event)
+    {
+        let thread = self.thread(&event.header, event.thread);
+        let time = Timestamp::from_nanos_since_reference(event.time);
+        match &event.data
+        {
+            EventData::Scope { .. } =>
+            {
+                let marker = self.marker(event, None);
+                self.profile.add_marker(thread, MarkerTiming::Interval(time, Timestamp::from_nanos_since_reference(event.end())), marker);
+            },
+            EventData::Counter(value) =>
+            {
+                let mut series = vec![(event.name.clone(), value)];
+                series.extend(event.args.iter().map(|(key, value)| (Text::Owned(format!("{} {key}", event.name)), value)));
+                for (name, value) in series
+                {
+                    let Some(value) = counter_value(value) else { continue };
+                    let (counter, last) = self.counter(&event.header, name.clone());
+                    // Counter samples are deltas, the profiler sums them up again
+                    self.profile.add_counter_sample(counter, time, value - last, 1);
+                    self.counters.insert((event.header.clone(), name), (counter, value));
+                }
+            },
+            EventData::Async(phase, id) =>
+            {
+                let (AsyncId::Id(raw) | AsyncId::Local(raw) | AsyncId::Global(raw)) = *id;
+                let key = SpanKey::Async(event.category.clone(), raw);
+                match phase
+                {
+                    AsyncPhase::Begin =>
+                    {
+                        let marker = self.marker(event, None);
+                        self.open.insert(key, (thread, event.time, marker));
+                    },
+                    AsyncPhase::Step =>
+                    {
+                        let marker = self.marker(event, None);
+                        self.profile.add_marker(thread, MarkerTiming::Instant(time), marker);
+                    },
+                    AsyncPhase::End => self.close(&key, time),
+                }
+            },
+            EventData::Object(object) =>
+            {
+                let (ObjectEvent::Created(id) | ObjectEvent::Snapshot(id, _) | ObjectEvent::Destroyed(id)) = object;
+                let key = SpanKey::Object(*id);
+                match object
+                {
+                    ObjectEvent::Created(_) =>
+                    {
+                        let marker = self.marker(event, None);
+                        self.open.insert(key, (thread, event.time, marker));
+                    },
+                    ObjectEvent::Snapshot(_, snapshot) =>
+                    {
+                        let marker = self.marker(event, Some(snapshot.to_string()));
+                        self.profile.add_marker(thread, MarkerTiming::Instant(time), marker);
+                    },
+                    ObjectEvent::Destroyed(_) => self.close(&key, time),
+                }
+            },
+            EventData::Instant(_) | EventData::Flow(..) =>
+            {
+                let flow = match event.data
+                {
+                    EventData::Flow(FlowPhase::Out, id) => Some(format!("flow {id:#x} out")),
+                    EventData::Flow(FlowPhase::Step, id) => Some(format!("flow {id:#x} step")),
+                    EventData::Flow(FlowPhase::In, id) => Some(format!("flow {id:#x} in")),
+                    _ => None,
+                };
+                let marker = self.marker(event, flow);
+                self.profile.add_marker(thread, MarkerTiming::Instant(time), marker);
+            },
+        }
+    }
+
+    fn close(&mut self, key: &SpanKey, end: Timestamp)
+    {
+        if let Some((thread, start, marker)) = self.open.remove(key)
+        {
+            self.profile.add_marker(thread, MarkerTiming::Interval(Timestamp::from_nanos_since_reference(start), end), marker);
+        }
+    }
+
+    /// The args are shown as json, `extra` replaces them if present
+    fn marker(&mut self, event: &Event, extra: Option<String>) -> ScoperMarker
+    {
+        let args = extra.unwrap_or_else(|| {
+            let args = crate::json::json_args(&event.static_args, &event.args);
+            if args.as_object().is_some_and(serde_json::Map::is_empty) { String::new() } else { args.to_string() }
+        });
+        let category = if event.category.is_empty()
+        {
+            CategoryHandle::OTHER
+        }
+        else
+        {
+            match self.categories.entry(event.category.clone())
+            {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => *entry.insert(self.profile.add_category(&event.category, CategoryColor::Blue)),
+            }
+        };
+        ScoperMarker {
+            marker_type: self.marker_type,
+            name: self.profile.intern_string(&event.name),
+            category,
+            args: self.profile.intern_string(&args),
+        }
+    }
+
+    fn process(&mut self, header: &Text) -> ProcessHandle
+    {
+        if let Some(&process) = self.processes.get(header)
+        {
+            return process;
+        }
+        let pid = u32::try_from(self.processes.len() + 1).unwrap_or(u32::MAX);
+        let name = MetaTrace::process_name(self.meta_traces, header);
+        let process = self.profile.add_process(name, pid, Timestamp::from_nanos_since_reference(0));
+        self.processes.insert(header.clone(), process);
+        process
+    }
+
+    fn thread(&mut self, header: &Text, tid: u64) -> ThreadHandle
+    {
+        if let Some(&thread) = self.threads.get(&(header.clone(), tid))
+        {
+            return thread;
+        }
+        let process = self.process(header);
+        // Main threads would be named after the process
+        let thread = self.profile.add_thread(process, u32::try_from(tid).unwrap_or(u32::MAX), Timestamp::from_nanos_since_reference(0), false);
+        self.profile.set_thread_name(thread, &MetaTrace::thread_name(self.meta_traces, header, tid));
+        self.profile.set_thread_show_markers_in_timeline(thread, true);
+        self.threads.insert((header.clone(), tid), thread);
+        thread
+    }
+
+    /// Handle of the counter together with its last value
+    fn counter(&mut self, header: &Text, name: Text) -> (CounterHandle, f64)
+    {
+        if let Some(&counter) = self.counters.get(&(header.clone(), name.clone()))
+        {
+            return counter;
+        }
+        let process = self.process(header);
+        // The profiler only draws graphs of unknown counters in the memory category
+        let counter = self.profile.add_counter(process, &name, "Memory", "Counter recorded with scoper");
+        self.counters.insert((header.clone(), name), (counter, 0.0));
+        (counter, 0.0)
+    }
+
+    fn finish(mut self) -> Profile
+    {
+        // Spans that never ended last until the end of the recording
+        for (thread, start, marker) in std::mem::take(&mut self.open).into_values()
+        {
+            self.profile.add_marker(thread, MarkerTiming::IntervalStart(Timestamp::from_nanos_since_reference(start)), marker);
+        }
+        self.profile
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn counter_value(value: &Value) -> Option<f64>
+{
+    match *value
+    {
+        Value::UInt(uint) => Some(uint as f64),
+        Value::IInt(iint) => Some(iint as f64),
+        Value::Float(float) => Some(float),
+        Value::Bool(bool) => Some(f64::from(u8::from(bool))),
+        Value::Str(_) => None,
+    }
+}
+
+struct ScoperMarker
+{
+    marker_type: MarkerTypeHandle,
+    name: StringHandle,
+    category: CategoryHandle,
+    args: StringHandle,
+}
+
+impl Marker for ScoperMarker
+{
+    fn marker_type(&self, _profile: &mut Profile) -> MarkerTypeHandle { self.marker_type }
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle { self.name }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle { self.category }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle { self.args }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 { unreachable!("The schema has no number fields") }
+}
@@ -0,0 +1,131 @@
+// Pixel positions do not need the full precision of the times
+#![allow(clippy::cast_precision_loss)]
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{folded, model::Event};
+
+const WIDTH: f64 = 1200.0;
+const PADDING: f64 = 10.0;
+const FRAME_HEIGHT: f64 = 16.0;
+const TITLE_HEIGHT: f64 = 40.0;
+/// Rough width of a character in the 12px font
+const CHAR_WIDTH: f64 = 7.0;
+
+/// Merged frames of the folded stacks, children are ordered by name
+#[derive(Default)]
+struct Node
+{
+    micros: u64,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node
+{
+    fn depth(&self) -> usize { self.children.values().map(|child| child.depth() + 1).max().unwrap_or_default() }
+}
+
+/// Flamegraph of the scopes in `events` as a standalone svg, wider frames took more time
+pub(crate) fn svg(events: &[Event], title: &str) -> String
+{
+    let mut root = Node::default();
+    for (stack, micros) in folded::stacks(events)
+    {
+        root.micros += micros;
+        let mut node = &mut root;
+        for frame in stack.split(';')
+        {
+            node = node.children.entry(frame.to_string()).or_default();
+            node.micros += micros;
+        }
+    }
+
+    let height = TITLE_HEIGHT + (root.depth() + 1) as f64 * FRAME_HEIGHT + PADDING;
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r##"<?xml version="1.0" standalone="no"?>
+<svg version="1.1" width="{WIDTH}" height="{height}" viewBox="0 0 {WIDTH} {height}" xmlns="http://www.w3.org/2000/svg">
+<rect x="0" y="0" width="100%" height="100%" fill="#f8f8f8"/>
+<text x="{}" y="24" text-anchor="middle" font-family="Verdana" font-size="17">{}</text>
+<g font-family="Verdana" font-size="12">"##,
+        WIDTH / 2.0,
+        escape(title)
+    );
+    let layout = Layout {
+        scale: if root.micros == 0 { 0.0 } else { (WIDTH - 2.0 * PADDING) / root.micros as f64 },
+        total: root.micros,
+        bottom: height - PADDING,
+    };
+    layout.frame(&mut svg, "all", &root, PADDING, 0);
+    svg.push_str("</g>\n</svg>\n");
+    svg
+}
+
+struct Layout
+{
+    /// Pixels per microsecond
+    scale: f64,
+    total: u64,
+    bottom: f64,
+}
+
+impl Layout
+{
+    /// Draws `node` and its children above it
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn frame(&self, svg: &mut String, name: &str, node: &Node, x: f64, depth: usize)
+    {
+        let width = node.micros as f64 * self.scale;
+        // Too thin to be seen
+        if width < 0.1
+        {
+            return;
+        }
+        let y = self.bottom - (depth + 1) as f64 * FRAME_HEIGHT;
+        let percent = if self.total == 0 { 0.0 } else { node.micros as f64 * 100.0 / self.total as f64 };
+        let _ = write!(
+            svg,
+            r#"<g><title>{} ({} us, {percent:.2}%)</title><rect x="{x:.1}" y="{y:.1}" width="{width:.1}" height="{}" fill="{}" rx="2" ry="2"/>"#,
+            escape(name),
+            node.micros,
+            FRAME_HEIGHT - 1.0,
+            color(name)
+        );
+        let fitting = (width / CHAR_WIDTH) as usize;
+        if fitting >= 3
+        {
+            let label = if name.chars().count() <= fitting
+            {
+                name.to_string()
+            }
+            else
+            {
+                format!("{}..", name.chars().take(fitting - 2).collect::<String>())
+            };
+            let _ = write!(svg, r#"<text x="{:.1}" y="{:.1}">{}</text>"#, x + 3.0, y + FRAME_HEIGHT - 4.5, escape(&label));
+        }
+        svg.push_str("</g>\n");
+
+        let mut x = x;
+        for (name, child) in &node.children
+        {
+            self.frame(svg, name, child, x, depth + 1);
+            x += child.micros as f64 * self.scale;
+        }
+    }
+}
+
+/// Warm colors that stay the same for a name across graphs
+fn color(name: &str) -> String
+{
+    // FNV-1a
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3));
+    let [red, green, blue, ..] = hash.to_le_bytes();
+    format!("rgb({},{},{})", 205 + u16::from(red) % 50, 80 + u16::from(green) % 150, u16::from(blue) % 55)
+}
+
+fn escape(text: &str) -> String
+{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
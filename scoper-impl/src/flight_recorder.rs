@@ -0,0 +1,62 @@
+use std::{io, path::Path, time::Duration};
+
+use crate::{
+    BufferLimits, Compression, Format, OverflowPolicy, RecordScope, TimePoint, json::chrome_trace, model::Event, session::{Session, active}
+};
+
+/// The part of a recording a flight recorder keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlightWindow
+{
+    /// The most recent events of all threads, scopes count from the moment they end
+    Events(usize),
+    /// The events of the most recent time span
+    Duration(Duration),
+}
+
+impl From<FlightWindow> for BufferLimits
+{
+    fn from(window: FlightWindow) -> Self
+    {
+        match window
+        {
+            FlightWindow::Events(count) => Self::new(count, OverflowPolicy::DropOldest),
+            FlightWindow::Duration(max_age) => Self::UNBOUNDED.with_max_age(max_age),
+        }
+    }
+}
+
+impl RecordScope
+{
+    /// Starts a recording that only keeps the most recent `window`
+    /// Use [`dump_flight_recorder`] to write it out, the rest is written when dropped
+    pub fn flight_recorder(path: impl AsRef<Path>, window: FlightWindow) -> Self { Self::start_with_limits(path, window.into()) }
+}
+
+/// Writes the window kept by the recording active on this thread as a chrome trace
+/// A `.gz` or `.zst` extension compresses it, the recorder keeps the events for later dumps and its own file
+///
+/// # Errors
+/// Returns an Error if no recording is active or the file can not be written
+pub fn dump_flight_recorder(path: impl AsRef<Path>) -> io::Result<()>
+{
+    let active = active().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No recording is active on this thread"))?;
+    let data = window(&active.session);
+    let path = Format::Json.output_path(path.as_ref());
+    let mut file = Compression::from_path(&path).create(&path)?;
+    serde_json::to_writer(&mut file, &data)?;
+    file.finish()
+}
+
+fn window(session: &Session) -> serde_json::Map<String, serde_json::Value>
+{
+    let now = TimePoint::now();
+    let zero = session.limits.max_age.and_then(|max_age| now.checked_sub(max_age)).unwrap_or(session.epoch);
+    let events = session.events(session.snapshot(now).into_iter(), zero);
+    let first = events.iter().map(|event| event.time).min().unwrap_or_default();
+    let mut events: Vec<_> = events.into_iter().filter_map(|event| event.shift(first)).collect();
+    Event::sort(&mut events);
+    let mut data = chrome_trace(&events, &session.meta_traces());
+    data.extend(session.chrome_extras());
+    data
+}
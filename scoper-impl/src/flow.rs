@@ -0,0 +1,49 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use scoper_base::Info;
+
+use crate::{
+    session::with_active,
+    types::{BaseInfo, FlowEvent, FlowPhase, Trace},
+};
+
+static NEXT_FLOW_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Arrow between scopes, possibly on different threads
+/// The flow events bind to the innermost open scope of the thread that emits them
+#[derive(Clone, Copy)]
+pub struct Flow
+{
+    info: Info,
+    id: u64,
+}
+
+impl Flow
+{
+    /// Creates a flow with a process wide unique id
+    #[must_use]
+    pub fn new(info: Info) -> Self
+    {
+        Self {
+            info,
+            id: NEXT_FLOW_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    #[must_use]
+    pub const fn with_id(info: Info, id: u64) -> Self { Self { info, id } }
+
+    fn push(self, phase: FlowPhase)
+    {
+        with_active(|active| active.push(Trace(BaseInfo::build_now(self.info), FlowEvent(phase, self.id))));
+    }
+}
+
+/// Starts the flow at the enclosing scope
+pub fn flow_out(flow: Flow) { flow.push(FlowPhase::Out); }
+
+/// Passes the flow through the enclosing scope
+pub fn flow_step(flow: Flow) { flow.push(FlowPhase::Step); }
+
+/// Ends the flow at the enclosing scope
+pub fn flow_in(flow: Flow) { flow.push(FlowPhase::In); }
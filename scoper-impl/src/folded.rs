@@ -0,0 +1,42 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{model::Event, nesting};
+
+/// Self time of every stack of scopes in microseconds, equal stacks of all threads are merged
+/// The frames are joined by `;` from the root down, stacks without any time are left out
+pub(crate) fn stacks(events: &[Event]) -> BTreeMap<String, u64>
+{
+    let mut stacks: BTreeMap<String, u64> = BTreeMap::new();
+    for thread in nesting::threads(events)
+    {
+        for (index, span) in thread.spans.iter().enumerate()
+        {
+            let stack = thread
+                .stack(index)
+                .into_iter()
+                .map(|index| frame_name(&thread.spans[index].event.name))
+                .collect::<Vec<_>>()
+                .join(";");
+            *stacks.entry(stack).or_default() += span.self_time();
+        }
+    }
+    stacks.retain(|_, time| {
+        *time /= 1000;
+        *time > 0
+    });
+    stacks
+}
+
+/// Brendan Gregg's folded stacks, one `a;b;c 1234` line per stack
+pub(crate) fn file(events: &[Event]) -> String
+{
+    let mut file = String::new();
+    for (stack, micros) in stacks(events)
+    {
+        let _ = writeln!(file, "{stack} {micros}");
+    }
+    file
+}
+
+/// `;` separates the frames and a line holds one stack
+fn frame_name(name: &str) -> String { name.replace([';', '\n', '\r'], "_") }
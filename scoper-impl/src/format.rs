@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+
+use crate::Compression;
+
+/// File format of a recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format
+{
+    /// Chrome trace event json, for `about://tracing`
+    #[default]
+    Json,
+    /// Perfetto `TracePacket` protobuf, loads much faster in `ui.perfetto.dev`
+    Perfetto,
+    /// Firefox Profiler processed profile, for `profiler.firefox.com`
+    Firefox,
+    /// Speedscope evented profiles of the scopes, for `www.speedscope.app`
+    Speedscope,
+    /// Folded stacks weighted by microseconds, for flamegraph tools
+    Folded,
+    /// Flamegraph of the scopes rendered as svg
+    Flamegraph,
+    /// pprof `profile.proto` of the scopes with inclusive and exclusive wall time, for `go tool pprof`
+    Pprof,
+    /// Callgrind call graph of the scopes with costs in microseconds, for `KCachegrind`
+    Callgrind,
+    /// OpenTelemetry OTLP/JSON lines of spans and gauges, like the file exporter of the collector writes them
+    Otlp,
+    /// Compact binary event log written while the events arrive, read it back with [`Trace`](crate::Trace) to convert it
+    Binary,
+}
+
+impl Format
+{
+    /// Format picked by the extension of `path`, json if it is unknown
+    /// A `.gz` or `.zst` extension is skipped
+    #[must_use]
+    pub fn from_path(path: &Path) -> Self
+    {
+        let path = &Compression::from_path(path).strip(path);
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        match path.extension().and_then(|extension| extension.to_str())
+        {
+            Some("perfetto-trace" | "pftrace") => Self::Perfetto,
+            Some("json") if name.ends_with(".firefox.json") => Self::Firefox,
+            Some("json") if name.ends_with(".speedscope.json") => Self::Speedscope,
+            Some("json") if name.ends_with(".otlp.json") => Self::Otlp,
+            Some("folded") => Self::Folded,
+            Some("svg") => Self::Flamegraph,
+            Some("pprof") => Self::Pprof,
+            Some("scoper") => Self::Binary,
+            Some("callgrind") => Self::Callgrind,
+            _ if name.starts_with("callgrind.out") => Self::Callgrind,
+            _ => Self::Json,
+        }
+    }
+
+    /// Json recordings always end in .json, followed by the extension of the compression if any
+    pub(crate) fn output_path(self, path: &Path) -> PathBuf
+    {
+        let compression = Compression::from_path(path);
+        let path = compression.strip(path);
+        let path = match self
+        {
+            Self::Json => path.with_extension("json"),
+            Self::Perfetto | Self::Firefox | Self::Speedscope | Self::Folded | Self::Flamegraph | Self::Pprof | Self::Callgrind | Self::Otlp | Self::Binary => path,
+        };
+        compression.append(path)
+    }
+}
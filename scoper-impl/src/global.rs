@@ -1,54 +1,58 @@
-use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::sync::Arc;
 
-use scoper_base::*;
+use scoper_base::{Args, AsyncId, AsyncPhase, Info, InstantScopeSize, Value};
 
 use crate::{
-    TimePoint, scopes::Start, types::{BaseInfo, TaggedTrace, Trace}
+    TimePoint, scopes::Start, session::with_active, strings::NameId, types::{AsyncEvent, BaseInfo, Trace}
 };
 
-pub fn record_custom_scope(info: Info, start: TimePoint, end: TimePoint)
+pub fn record_custom_scope(info: Info, start: TimePoint, end: TimePoint) { record_scope_with_args(info, start, end, Args::new(), None); }
+
+pub(crate) fn record_scope_with_args(info: Info, start: TimePoint, end: TimePoint, args: Args, name: Option<(NameId, Arc<str>)>)
 {
-    SCOPES.push(Trace(BaseInfo::build(info, end), Start(start)));
+    with_active(|active| {
+        // Scopes can be opened while another session was active
+        let name = name.map(|(id, name)| if id.session() == active.session.id { id } else { active.session.intern(&name).0 });
+        let base = BaseInfo::build(info, end).with_args(args).with_name(name);
+        active.push(Trace(base, Start(start)));
+    });
 }
 
-pub fn record_custom_value(info: Info, value: Value)
+pub fn record_custom_value(info: Info, value: Value) { record_custom_value_with_args(info, value, Args::new()); }
+
+/// Records a counter, the args are additional series of the counter
+pub fn record_custom_value_with_args(info: Info, value: Value, args: Args)
 {
-    COUNTERS.push(Trace(BaseInfo::build_now(info), value));
+    with_active(|active| active.push(Trace(BaseInfo::build_now(info).with_args(args), value)));
 }
 
-pub fn record_custom_instant(info: Info, scope_size: InstantScopeSize) { INSTANCES.push(Trace(BaseInfo::build_now(info), scope_size)); }
-
-pub(super) fn flush_buffers() -> impl Iterator<Item = TaggedTrace>
+/// Records a counter with a name built at runtime, the static name of `info` is replaced
+pub fn record_custom_value_named(info: Info, name: &str, value: Value, args: Args)
 {
-    SCOPES
-        .flush()
-        .into_iter()
-        .map(Trace::tag)
-        .chain(COUNTERS.flush().into_iter().map(Trace::tag))
-        .chain(INSTANCES.flush().into_iter().map(Trace::tag))
+    with_active(|active| {
+        let base = BaseInfo::build_now(info).with_args(args).with_name(Some(active.session.intern(name).0));
+        active.push(Trace(base, value));
+    });
 }
 
-static SCOPES: Buffer<Trace<Start>> = Buffer::init::<30_000>();
-static COUNTERS: Buffer<Trace<Value>> = Buffer::init::<1024>();
-static INSTANCES: Buffer<Trace<InstantScopeSize>> = Buffer::init::<128>();
+pub fn record_custom_instant(info: Info, scope_size: InstantScopeSize) { record_custom_instant_with_args(info, scope_size, Args::new()); }
 
-struct Buffer<Data>
+pub fn record_custom_instant_with_args(info: Info, scope_size: InstantScopeSize, args: Args)
 {
-    buffer: LazyLock<Mutex<Vec<Data>>>,
+    with_active(|active| active.push(Trace(BaseInfo::build_now(info).with_args(args), scope_size)));
 }
 
-impl<Data> Buffer<Data>
+/// Records an instant with a name built at runtime, the static name of `info` is replaced
+pub fn record_custom_instant_named(info: Info, name: &str, scope_size: InstantScopeSize, args: Args)
 {
-    const fn init<const CAPACITY: usize>() -> Self
-    {
-        Self {
-            buffer: LazyLock::new(const { || Mutex::new(Vec::with_capacity(CAPACITY)) }),
-        }
-    }
-
-    fn access(&self) -> MutexGuard<'_, Vec<Data>> { self.buffer.lock().expect("Could not get access") }
-
-    fn push(&self, value: Data) { self.access().push(value); }
+    with_active(|active| {
+        let base = BaseInfo::build_now(info).with_args(args).with_name(Some(active.session.intern(name).0));
+        active.push(Trace(base, scope_size));
+    });
+}
 
-    fn flush(&self) -> Vec<Data> { std::mem::take(&mut self.access()) }
+/// Records a single async event, the span is identified by its category and id
+pub fn record_custom_async(info: Info, id: AsyncId, phase: AsyncPhase)
+{
+    with_active(|active| active.push(Trace(BaseInfo::build_now(info), AsyncEvent(phase, id))));
 }
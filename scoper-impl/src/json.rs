@@ -1,170 +1,299 @@
-use std::thread::ThreadId;
+use std::{
+    io::{self, Write},
+    thread::ThreadId,
+};
 
-use scoper_base::{TraceInfo, Value};
+use scoper_base::{AsyncId, AsyncPhase, Value};
 use serde_json::{Map, Number, Value as JsonValue, json};
 
 use crate::{
-    event_types::EventType, global::{self}, record_scope::MetaTrace, types::{TaggedData, TaggedTrace, Trace}, RecordScope, TimePoint
+    event_types::EventType, model::{Event, EventData, Text}, record_scope::MetaTrace, recording::Recording, session::Session, sink::{RecordingInfo, TraceSink}, types::{FlowPhase, ObjectEvent, TaggedTrace}, RecordScope, TimePoint
 };
 
 impl RecordScope
 {
-    pub(crate) fn fetch_data(&mut self) -> Map<String, JsonValue>
+    /// Events of the whole recording relative to the starting time, in order
+    pub(crate) fn fetch_events(&mut self) -> std::io::Result<Vec<Event>>
     {
-        let mut traces: Vec<_> = global::flush_buffers().collect();
-
-        traces.sort_by(TaggedTrace::cmp_start);
+        self.session.expire(TimePoint::now());
+        let mut events = self.session.events(self.session.flush(), self.record_start);
 
-        let traces = traces.iter().filter_map(|t| t.json_format(self.record_start));
+        let spilled = self.session.take_spilled()?;
+        if !spilled.is_empty()
+        {
+            let offset = self.spill_offset();
+            // Spilled events happened first, the stable sort keeps them first on equal times
+            events = spilled.into_iter().filter_map(|event| event.shift(offset)).chain(events).collect();
+        }
 
-        let traces: Vec<_> = self.meta_traces.iter().map(MetaTrace::json_format).chain(traces).collect();
-        let mut data = Map::new();
-        data.insert("traceEvents".to_string(), traces.into());
-        data.insert("displayTimeUnit".to_string(), json!("ms")); //ns allowed as well
-        data.append(&mut self.meta_data);
-        
-        data
+        Event::sort(&mut events);
+        Ok(events)
     }
+
+    /// Spilled events are relative to the epoch of the session
+    pub(crate) fn spill_offset(&self) -> u64 { self.record_start.duration_since(self.session.epoch).as_nanos().try_into().unwrap_or(u64::MAX) }
 }
 
-#[allow(dead_code)]
-// Viewer does not handle negative well
-fn signed_time(earlier: TimePoint, later: TimePoint) -> i128
+/// Writes a chrome trace while the events arrive, only the metadata is held until finished
+pub struct ChromeJsonSink<W: Write>
 {
-    if let Some(pdur) = later.checked_duration_since(earlier)
+    writer: W,
+    metadata: Map<String, JsonValue>,
+    /// Trace events written so far
+    written: usize,
+}
+
+impl<W: Write> ChromeJsonSink<W>
+{
+    pub fn new(writer: W) -> Self
     {
-        pdur.as_micros().try_into().unwrap()
+        Self {
+            writer,
+            metadata: Map::new(),
+            written: 0,
+        }
     }
-    else
+
+    /// The writer, with the whole trace written to it if finished
+    pub fn into_inner(self) -> W { self.writer }
+
+    fn trace_event(&mut self, trace: &JsonValue) -> io::Result<()>
     {
-        -TryInto::<i128>::try_into(earlier.duration_since(later).as_micros()).unwrap()
+        if self.written > 0
+        {
+            self.writer.write_all(b",")?;
+        }
+        self.written += 1;
+        Ok(serde_json::to_writer(&mut self.writer, trace)?)
     }
 }
 
-impl TaggedTrace
+impl<W: Write + Send> TraceSink for ChromeJsonSink<W>
 {
-    fn start(&self) -> &TimePoint
+    fn begin(&mut self, info: &RecordingInfo) -> io::Result<()>
     {
-        match &self.1
-        {
-            TaggedData::Scope(start) => &start.0,
-            _ => self.end(),
-        }
+        self.metadata.clone_from(&info.metadata);
+        self.writer.write_all(b"{\"traceEvents\":[")
     }
 
-    fn end(&self) -> &TimePoint
+    fn process_name(&mut self, header: &str, name: &str) -> io::Result<()>
     {
-        &self.0.time_point
+        self.trace_event(&MetaTrace::ProcessName(header.to_string().into(), name.to_string()).json_format())
     }
 
-    fn cmp_start(&self, other: &TaggedTrace) -> std::cmp::Ordering
+    fn thread_name(&mut self, header: &str, thread: u64, name: &str) -> io::Result<()>
     {
-        match self.start().cmp(other.start())
+        self.trace_event(&MetaTrace::ThreadName(header.to_string().into(), thread, name.to_string()).json_format())
+    }
+
+    fn events(&mut self, events: &[Event]) -> io::Result<()>
+    {
+        for event in events
+        {
+            self.trace_event(&event.json_format())?;
+        }
+        Ok(())
+    }
+
+    /// Same as [`Recording::chrome_data`] from here on
+    fn finish(&mut self) -> io::Result<()>
+    {
+        self.writer.write_all(b"],\"displayTimeUnit\":\"ms\"")?;
+        for (key, value) in std::mem::take(&mut self.metadata)
         {
-            std::cmp::Ordering::Equal => other.end().cmp(self.end()),
-            c => c,
+            self.writer.write_all(b",")?;
+            serde_json::to_writer(&mut self.writer, &key)?;
+            self.writer.write_all(b":")?;
+            serde_json::to_writer(&mut self.writer, &value)?;
         }
+        self.writer.write_all(b"}")?;
+        self.writer.flush()
+    }
+}
+
+impl Session
+{
+    /// Owned events of `traces` relative to `zero`, not ordered yet
+    pub(crate) fn events(&self, traces: impl Iterator<Item = TaggedTrace>, zero: TimePoint) -> Vec<Event>
+    {
+        let strings = self.strings();
+        traces.filter_map(|trace| trace.into_event(zero, &strings)).collect()
+    }
+}
+
+/// Chrome trace object of `events` together with the names of threads and headers
+pub(crate) fn chrome_trace(events: &[Event], meta_traces: &[MetaTrace]) -> Map<String, JsonValue>
+{
+    let traces: Vec<_> = meta_traces.iter().map(MetaTrace::json_format).chain(events.iter().map(Event::json_format)).collect();
+    let mut data = Map::new();
+    data.insert("traceEvents".to_string(), traces.into());
+    data.insert("displayTimeUnit".to_string(), json!("ms")); //ns allowed as well
+    data
+}
+
+impl Recording
+{
+    pub(crate) fn chrome_data(&self) -> Map<String, JsonValue>
+    {
+        let mut data = chrome_trace(&self.events, &self.meta_traces);
+        data.extend(self.extras.clone());
+        data
+    }
+}
+
+#[allow(dead_code)]
+// Viewer does not handle negative well
+fn signed_time(earlier: TimePoint, later: TimePoint) -> i128
+{
+    if let Some(pdur) = later.checked_duration_since(earlier)
+    {
+        pdur.as_micros().try_into().unwrap()
+    }
+    else
+    {
+        -TryInto::<i128>::try_into(earlier.duration_since(later).as_micros()).unwrap()
     }
 }
 
-impl TaggedTrace
+impl Event
 {
     fn code(&self) -> char
     {
-        match self.1
+        match self.data
         {
-            TaggedData::Scope(_) => EventType::Scope.code(),
-            TaggedData::Counter(_) => EventType::Counter.code(),
-            TaggedData::Instant(_) => EventType::Instant.code(),
+            EventData::Scope { .. } => EventType::Scope.code(),
+            EventData::Counter(_) => EventType::Counter.code(),
+            EventData::Instant(_) => EventType::Instant.code(),
+            EventData::Async(AsyncPhase::Begin, _) => EventType::AsyncStart.code(),
+            EventData::Async(AsyncPhase::Step, _) => EventType::AsyncProgress.code(),
+            EventData::Async(AsyncPhase::End, _) => EventType::AsyncFinish.code(),
+            EventData::Flow(FlowPhase::Out, _) => EventType::FlowStart.code(),
+            EventData::Flow(FlowPhase::Step, _) => EventType::FlowProgress.code(),
+            EventData::Flow(FlowPhase::In, _) => EventType::FlowFinish.code(),
+            EventData::Object(ObjectEvent::Created(_)) => EventType::ObjectCreated.code(),
+            EventData::Object(ObjectEvent::Snapshot(..)) => EventType::ObjectSnapshot.code(),
+            EventData::Object(ObjectEvent::Destroyed(_)) => EventType::ObjectDestroyed.code(),
         }
     }
 
-    fn json_format(&self, zero: TimePoint) -> Option<JsonValue>
+    #[allow(clippy::items_after_statements)]
+    pub(crate) fn json_format(&self) -> JsonValue
     {
-        // Viewer does not handle negative well
-        //let time_stamp = signed_time(zero, base.start);
-        let time_point = self.0.time_point.checked_duration_since(zero)?.as_micros();
-
-        let &TraceInfo {
-            name,
-            category,
-            header,
-            args,
-        } = self.0.info;
-
         let mut ret = json!({
-            "name": name,
-            "cat": category,
-            "pid": header,
-            "tid": print_tread_id(&self.0.thread_id),
+            "name": self.name,
+            "cat": self.category,
+            "pid": self.header,
+            "tid": self.thread,
             "ph": self.code(),
-            "ts": time_point,
-            "args": args,
+            "ts": self.time / 1000,
+            "args": json_args(&self.static_args, &self.args),
         });
 
-        adjust_specific_atributes(ret.as_object_mut().unwrap(), self, zero);
-        fn adjust_specific_atributes(ret: &mut Map<String, JsonValue>, Trace(base, tag): &TaggedTrace, zero: TimePoint)
+        adjust_specific_atributes(ret.as_object_mut().unwrap(), self);
+        fn adjust_specific_atributes(ret: &mut Map<String, JsonValue>, event: &Event)
         {
-            use TaggedData::*;
-            match tag
+            match &event.data
             {
-                Scope(start) =>
+                EventData::Scope { .. } =>
                 {
-                    let start = start.0.duration_since(zero).as_micros();
-                    let dur = base.time_point.duration_since(zero).as_micros() - start;
-                    ret["ts"] = json!(start);
+                    let dur = event.end() / 1000 - event.time / 1000;
                     ret.insert("dur".to_string(), json!(dur));
                 },
-                Counter(value) =>
+                EventData::Counter(value) =>
                 {
-                    let args = &mut ret["args"];
-                    let mut extra_args = std::mem::replace(args, value.as_args());
-
-                    if let Some(valid_map) = extra_args.as_object_mut()
-                    {
-                        args.as_object_mut().unwrap().append(valid_map);
-                    }
-                    else if !extra_args.is_null()
+                    ret["args"][""] = value.json_format();
+                },
+                EventData::Instant(scope_size) =>
+                {
+                    ret.insert("s".to_string(), json!(scope_size.code()));
+                },
+                EventData::Async(_, id) =>
+                {
+                    let (key, id) = id.json_format();
+                    ret.insert(key.to_string(), id);
+                },
+                EventData::Flow(phase, id) =>
+                {
+                    ret.insert("id".to_string(), json!(format!("{id:#x}")));
+                    if let FlowPhase::In = phase
                     {
-                        args["args"] = extra_args;
+                        // Binds to the enclosing scope instead of the next one
+                        ret.insert("bp".to_string(), json!("e"));
                     }
                 },
-                Instant(scope_size) =>
+                EventData::Object(object) =>
                 {
-                    ret.insert("s".to_string(), json!(scope_size.code()));
+                    let (ObjectEvent::Created(id) | ObjectEvent::Snapshot(id, _) | ObjectEvent::Destroyed(id)) = object;
+                    ret.insert("id".to_string(), json!(format!("{id:#x}")));
+                    if let ObjectEvent::Snapshot(_, snapshot) = object
+                    {
+                        ret["args"]["snapshot"] = snapshot.clone();
+                    }
                 },
             }
         }
 
-        Some(ret)
+        ret
+    }
+}
+
+/// Chrome expects the args as an object
+/// Static args that are not a json object are kept under "args"
+pub(crate) fn json_args(static_args: &str, args: &[(Text, Value)]) -> JsonValue
+{
+    let mut ret = if static_args.is_empty()
+    {
+        Map::new()
+    }
+    else if let Ok(JsonValue::Object(map)) = serde_json::from_str(static_args)
+    {
+        map
     }
+    else
+    {
+        Map::from_iter([("args".to_string(), json!(static_args))])
+    };
+    ret.extend(args.iter().map(|(key, value)| (key.to_string(), value.json_format())));
+    JsonValue::Object(ret)
 }
 
 trait ValueExt
 {
-    fn as_number(&self) -> Number;
-    fn as_args(&self) -> JsonValue;
+    fn json_format(&self) -> JsonValue;
 }
 
 impl ValueExt for Value
 {
-    fn as_number(&self) -> Number
+    #[allow(clippy::enum_glob_use)]
+    fn json_format(&self) -> JsonValue
     {
         use scoper_base::Value::*;
-        match *self
+        match self
         {
-            UInt(uint) => Number::from_u128(uint.into()),
-            IInt(iint) => Number::from_i128(iint.into()),
-            Float(float) => Number::from_f64(float),
+            UInt(uint) => json!(uint),
+            IInt(iint) => json!(iint),
+            Float(float) => Number::from_f64(*float).map_or(JsonValue::Null, JsonValue::Number),
+            Bool(bool) => json!(bool),
+            Str(str) => json!(str),
         }
-        .unwrap()
     }
+}
+
+trait AsyncIdExt
+{
+    fn json_format(&self) -> (&'static str, JsonValue);
+}
 
-    fn as_args(&self) -> JsonValue
+impl AsyncIdExt for AsyncId
+{
+    fn json_format(&self) -> (&'static str, JsonValue)
     {
-        json!({
-            "": self.as_number(),
-        })
+        match *self
+        {
+            AsyncId::Id(id) => ("id", json!(format!("{id:#x}"))),
+            AsyncId::Local(id) => ("id2", json!({"local": format!("{id:#x}")})),
+            AsyncId::Global(id) => ("id2", json!({"global": format!("{id:#x}")})),
+        }
     }
 }
 
@@ -175,7 +304,7 @@ impl MetaTrace
         let (pid, tid, name) = match self
         {
             MetaTrace::ProcessName(pid, name) => (pid, 0, name),
-            MetaTrace::ThreadName(pid, tid, name) => (pid, print_tread_id(tid), name),
+            MetaTrace::ThreadName(pid, tid, name) => (pid, *tid, name),
             /* Not in doc
             MetaEvent::ProcessUptimeSeconds(pid, uptime) => serde_json::json!({
                 "args": {"uptime": uptime},
@@ -212,10 +341,10 @@ impl MetaTrace
 }
 
 // Thread_id::as_u64() is stablized this is not needed
-fn print_tread_id(tid: &ThreadId) -> u64
+pub(crate) fn print_tread_id(tid: ThreadId) -> u64
 {
     // results in ThreadId(3)
-    let mut tid = format!("{:?}", tid).split_off(9);
+    let mut tid = format!("{tid:?}").split_off(9);
     tid.pop();
     tid.parse().unwrap()
 }
\ No newline at end of file
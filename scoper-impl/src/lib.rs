@@ -1,23 +1,70 @@
 #![feature(adt_const_params)]
 #![warn(clippy::all, clippy::perf, clippy::pedantic)]
 
+mod async_span;
+mod binary;
+mod callgrind;
+mod capture;
+mod chrome;
+mod compression;
 mod global;
 mod json;
+mod limits;
 mod macro_rules;
+mod merge;
+mod model;
+mod nesting;
+mod object;
+mod otlp;
+mod perfetto;
+mod pprof;
 mod record_scope;
+mod recording;
 mod event_types;
+mod firefox;
+mod flamegraph;
+mod flight_recorder;
+mod flow;
+mod folded;
+mod format;
 mod scopes;
+mod session;
+mod sink;
+mod speedscope;
+mod stats;
+mod strings;
+mod trace;
 mod types;
 
 pub use record_scope::RecordScope;
-pub use global::{record_custom_instant, record_custom_scope, record_custom_value};
-pub use scopes::Scope;
+pub use async_span::AsyncSpan;
+pub use binary::{BinarySink, BinaryTrace};
+pub use capture::{CapturedScope, CapturedTrace, capture};
+pub use chrome::{ChromeEvent, ChromeId, ChromeTrace, Phase};
+pub use limits::{BufferLimits, OverflowPolicy};
+pub use flight_recorder::{FlightWindow, dump_flight_recorder};
+pub use compression::Compression;
+pub use format::Format;
+pub use json::ChromeJsonSink;
+pub use model::{Event, EventData};
+pub use sink::{FileSink, RecordingInfo, TraceSink};
+pub use types::{FlowPhase, ObjectEvent};
+pub use flow::{Flow, flow_in, flow_out, flow_step};
+pub use object::{Traced, TracedObject};
+pub use global::{
+    record_custom_async, record_custom_instant, record_custom_instant_named, record_custom_instant_with_args, record_custom_scope,
+    record_custom_value, record_custom_value_named, record_custom_value_with_args,
+};
+pub use scopes::{Scope, record_arg};
+pub use session::{SessionGuard, SessionHandle};
+pub use stats::{Metric, ScopeDiff, ScopeStats, diff};
+pub use trace::{Trace, convert};
 
 pub mod macros
 {
-    pub use scoper_attr::record;
+    pub use scoper_attr::{TracedObject, record};
 
-    pub use crate::{record_instant, record_scope, record_value};
+    pub use crate::{record_async, record_flow, record_instant, record_scope, record_value, trace_info};
 
     #[doc(hidden)]
     pub mod hidden_reexport
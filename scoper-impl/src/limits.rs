@@ -0,0 +1,137 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use serde_json::{Map, Value as JsonValue};
+
+use crate::model::EventData;
+
+/// What happens to a new event once the buffer of its kind is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy
+{
+    /// Discards the new event
+    #[default]
+    DropNewest,
+    /// Discards the oldest buffered event of any kind and thread, all threads share one ring
+    /// Scopes are buffered when they end
+    DropOldest,
+    /// Moves the buffered events of all threads into a spill file next to the output
+    FlushToDisk,
+    /// Waits until the buffer is emptied by [`RecordScope::flush_to_disk`](crate::RecordScope::flush_to_disk)
+    /// Blocks forever if no other thread flushes the recording
+    Block,
+}
+
+/// Memory limits of a recording
+/// The capacity bounds the events buffered by all threads of the recording together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferLimits
+{
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+    /// Events older than this are dropped while recording
+    pub max_age: Option<Duration>,
+}
+
+impl BufferLimits
+{
+    pub const UNBOUNDED: Self = Self::new(usize::MAX, OverflowPolicy::DropNewest);
+
+    #[must_use]
+    pub const fn new(capacity: usize, policy: OverflowPolicy) -> Self
+    {
+        Self {
+            capacity,
+            policy,
+            max_age: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_max_age(mut self, max_age: Duration) -> Self
+    {
+        self.max_age = Some(max_age);
+        self
+    }
+}
+
+impl Default for BufferLimits
+{
+    fn default() -> Self { Self::UNBOUNDED }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum EventKind
+{
+    Scope,
+    Counter,
+    Instant,
+    Async,
+    Flow,
+    Object,
+}
+
+impl EventKind
+{
+    const ALL: [Self; 6] = [Self::Scope, Self::Counter, Self::Instant, Self::Async, Self::Flow, Self::Object];
+
+    const fn name(self) -> &'static str
+    {
+        match self
+        {
+            Self::Scope => "scopes",
+            Self::Counter => "counters",
+            Self::Instant => "instants",
+            Self::Async => "asyncs",
+            Self::Flow => "flows",
+            Self::Object => "objects",
+        }
+    }
+}
+
+impl EventKind
+{
+    pub(crate) const fn of(data: &EventData) -> Self
+    {
+        match data
+        {
+            EventData::Scope { .. } => Self::Scope,
+            EventData::Counter(_) => Self::Counter,
+            EventData::Instant(_) => Self::Instant,
+            EventData::Async(..) => Self::Async,
+            EventData::Flow(..) => Self::Flow,
+            EventData::Object(_) => Self::Object,
+        }
+    }
+}
+
+/// Counts the events lost to the [`OverflowPolicy`]
+#[derive(Default)]
+pub(crate) struct DroppedEvents
+{
+    kinds: [AtomicU64; EventKind::ALL.len()],
+    /// Spills that could not be written, their events are counted as well
+    failed_spills: AtomicU64,
+}
+
+impl DroppedEvents
+{
+    pub(crate) fn add(&self, kind: EventKind) { self.kinds[kind as usize].fetch_add(1, Ordering::Relaxed); }
+
+    pub(crate) fn add_failed_spill(&self) { self.failed_spills.fetch_add(1, Ordering::Relaxed); }
+
+    /// Metadata record of the dropped events, `None` if nothing was lost
+    pub(crate) fn json_format(&self) -> Option<JsonValue>
+    {
+        let counts: Map<_, _> = EventKind::ALL
+            .into_iter()
+            .map(|kind| (kind.name().to_string(), self.kinds[kind as usize].load(Ordering::Relaxed)))
+            .chain([("failedSpills".to_string(), self.failed_spills.load(Ordering::Relaxed))])
+            .filter(|(_, count)| *count > 0)
+            .map(|(name, count)| (name, count.into()))
+            .collect();
+        (!counts.is_empty()).then_some(JsonValue::Object(counts))
+    }
+}
@@ -1,30 +1,54 @@
+/// Creates a `TraceInfo` with the category filled in from the module path, usable in `const` and `static` items
+#[macro_export]
+macro_rules! trace_info {
+    ($name: expr) => {
+        scoper::TraceInfo::new($name)
+            .with_category($crate::macros::hidden_reexport::str_replace!(::std::module_path!(), "::", ","))
+    };
+}
+
 #[macro_export]
 macro_rules! record_scope {
-    ($header: expr, $name: expr) => {
-        static TRACE_SCOPE_INFO: scoper::TraceInfo = scoper::TraceInfo {
-            name: $name,
-            category: $crate::macros::hidden_reexport::str_replace!(::std::module_path!(), "::", ","),
-            header: $header,
-            args: "",
-        };
+    (dyn $name: expr $(, $key: ident = $value: expr)* $(; $($flow: ident = $id: expr),+)?) => {
+        record_scope!("", dyn $name $(, $key = $value)* $(; $($flow = $id),+)?)
+    };
+    ($header: expr, dyn $name: expr $(, $key: ident = $value: expr)* $(; $($flow: ident = $id: expr),+)?) => {
+        static TRACE_SCOPE_INFO: scoper::TraceInfo = $crate::trace_info!("").with_header($header);
         #[allow(unused)]
-        let _profiling_scope = $crate::Scope::start(&TRACE_SCOPE_INFO);
+        let _profiling_scope = $crate::Scope::start_named(&TRACE_SCOPE_INFO, &$name);
+        $( _profiling_scope.arg(::std::stringify!($key), $value); )*
+        $($( $crate::$flow($id); )+)?
     };
-    ($name: expr) => {
-        record_scope!("", $name)
+    ($name: expr $(, $key: ident = $value: expr)* $(; $($flow: ident = $id: expr),+)?) => {
+        record_scope!("", $name $(, $key = $value)* $(; $($flow = $id),+)?)
+    };
+    ($header: expr, $name: expr $(, $key: ident = $value: expr)* $(; $($flow: ident = $id: expr),+)?) => {
+        static TRACE_SCOPE_INFO: scoper::TraceInfo = $crate::trace_info!($name).with_header($header);
+        #[allow(unused)]
+        let _profiling_scope = $crate::Scope::start(&TRACE_SCOPE_INFO);
+        $( _profiling_scope.arg(::std::stringify!($key), $value); )*
+        $($( $crate::$flow($id); )+)?
     };
 }
 
 #[macro_export]
 macro_rules! record_value {
-    ($header: expr, $name: expr, $value: expr) => {{
-        static TRACE_COUNTER_INFO: scoper::TraceInfo = scoper::TraceInfo {
-            name: $name,
-            category: $crate::macros::hidden_reexport::str_replace!(::std::module_path!(), "::", ","),
-            header: $header,
-            args: "",
-        };
-        $crate::record_custom_value(&TRACE_COUNTER_INFO, $value);
+    ($header: expr, dyn $name: expr, $value: expr $(, $key: ident = $arg: expr)*) => {{
+        static TRACE_COUNTER_INFO: scoper::TraceInfo = $crate::trace_info!("").with_header($header);
+        $crate::record_custom_value_named(
+            &TRACE_COUNTER_INFO,
+            &$name,
+            $value,
+            ::std::vec![$((::std::stringify!($key), scoper::Value::from($arg))),*],
+        );
+    }};
+    ($header: expr, $name: expr, $value: expr $(, $key: ident = $arg: expr)*) => {{
+        static TRACE_COUNTER_INFO: scoper::TraceInfo = $crate::trace_info!($name).with_header($header);
+        $crate::record_custom_value_with_args(
+            &TRACE_COUNTER_INFO,
+            $value,
+            ::std::vec![$((::std::stringify!($key), scoper::Value::from($arg))),*],
+        );
     }}; /*
         ($name: expr) => {
             record_value!("", $name)
@@ -34,19 +58,76 @@ macro_rules! record_value {
 
 #[macro_export]
 macro_rules! record_instant {
-    ($header: expr, $name: expr, $scope_size: expr) => {{
-        static TRACE_INSTANT_INFO: scoper::TraceInfo = scoper::TraceInfo {
-            name: $name,
-            category: $crate::macros::hidden_reexport::str_replace!(::std::module_path!(), "::", ","),
-            header: $header,
-            args: "",
-        };
-        $crate::record_custom_instant(&TRACE_INSTANT_INFO, $scope_size);
+    (dyn $name: expr $(, $key: ident = $value: expr)*) => {
+        record_instant!("", dyn $name, scoper::InstantScopeSize::Process $(, $key = $value)*);
+    };
+    (dyn $name: expr, $scope_size: expr $(, $key: ident = $value: expr)*) => {
+        record_instant!("", dyn $name, $scope_size $(, $key = $value)*);
+    };
+    ($header: expr, dyn $name: expr, $scope_size: expr $(, $key: ident = $value: expr)*) => {{
+        static TRACE_INSTANT_INFO: scoper::TraceInfo = $crate::trace_info!("").with_header($header);
+        $crate::record_custom_instant_named(
+            &TRACE_INSTANT_INFO,
+            &$name,
+            $scope_size,
+            ::std::vec![$((::std::stringify!($key), scoper::Value::from($value))),*],
+        );
+    }};
+    ($name: expr $(, $key: ident = $value: expr)+) => {
+        record_instant!("", $name, scoper::InstantScopeSize::Process $(, $key = $value)+);
+    };
+    ($name: expr, $scope_size: expr $(, $key: ident = $value: expr)+) => {
+        record_instant!("", $name, $scope_size $(, $key = $value)+);
+    };
+    ($header: expr, $name: expr, $scope_size: expr $(, $key: ident = $value: expr)*) => {{
+        static TRACE_INSTANT_INFO: scoper::TraceInfo = $crate::trace_info!($name).with_header($header);
+        $crate::record_custom_instant_with_args(
+            &TRACE_INSTANT_INFO,
+            $scope_size,
+            ::std::vec![$((::std::stringify!($key), scoper::Value::from($value))),*],
+        );
     }};
     ($name: expr, $scope_size: expr) => {
         record_instant!("", $name, $scope_size);
     };
     ($name: expr) => {
-        record_instant!($name, $crate::InstantScopeSize::Process);
+        record_instant!($name, scoper::InstantScopeSize::Process);
+    };
+}
+
+#[macro_export]
+macro_rules! record_async {
+    ($name: expr, $id: expr $(, $key: ident = $value: expr)+ $(; category = $category: expr)?) => {
+        record_async!("", $name, $id $(, $key = $value)+ $(; category = $category)?)
+    };
+    ($header: expr, $name: expr, $id: expr $(, $key: ident = $value: expr)*; category = $category: expr) => {{
+        static TRACE_ASYNC_INFO: scoper::TraceInfo = scoper::TraceInfo::new($name).with_category($category).with_header($header);
+        $crate::AsyncSpan::begin_with_args(
+            &TRACE_ASYNC_INFO,
+            $id,
+            ::std::vec![$((::std::stringify!($key), scoper::Value::from($value))),*],
+        )
+    }};
+    ($header: expr, $name: expr, $id: expr $(, $key: ident = $value: expr)*) => {{
+        static TRACE_ASYNC_INFO: scoper::TraceInfo = $crate::trace_info!($name).with_header($header);
+        $crate::AsyncSpan::begin_with_args(
+            &TRACE_ASYNC_INFO,
+            $id,
+            ::std::vec![$((::std::stringify!($key), scoper::Value::from($value))),*],
+        )
+    }};
+    ($name: expr, $id: expr $(; category = $category: expr)?) => {
+        record_async!("", $name, $id $(; category = $category)?)
+    };
+}
+
+#[macro_export]
+macro_rules! record_flow {
+    ($header: expr, $name: expr) => {{
+        static TRACE_FLOW_INFO: scoper::TraceInfo = $crate::trace_info!($name).with_header($header);
+        $crate::Flow::new(&TRACE_FLOW_INFO)
+    }};
+    ($name: expr) => {
+        record_flow!("", $name)
     };
 }
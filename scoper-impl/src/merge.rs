@@ -0,0 +1,156 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::model::Event;
+
+/// Most runs read at the same time, more are merged into longer runs first
+const MAX_OPEN_RUNS: usize = 64;
+
+/// Ordered part of a spill file
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Run
+{
+    /// Byte offset of the first event
+    pub start: u64,
+    pub len: usize,
+}
+
+/// The events of all runs of a spill file in order, the file is removed when dropped
+/// Only the next event of every run is kept in memory
+pub(crate) struct Merge
+{
+    path: Option<PathBuf>,
+    runs: Vec<RunReader>,
+    /// Next event of every run
+    heads: Vec<Option<Event>>,
+    /// Order of [`Event::sort`], runs spilled earlier come first on equal times
+    queue: BinaryHeap<Reverse<(u64, Reverse<u64>, usize)>>,
+}
+
+impl Merge
+{
+    pub(crate) fn empty() -> Self
+    {
+        Self {
+            path: None,
+            runs: Vec::new(),
+            heads: Vec::new(),
+            queue: BinaryHeap::new(),
+        }
+    }
+
+    /// Merges `runs` of the spill file at `path`, which is owned by the merge from now on
+    pub(crate) fn new(mut path: PathBuf, mut runs: Vec<Run>) -> io::Result<Self>
+    {
+        let mut pass = 0;
+        while runs.len() > MAX_OPEN_RUNS
+        {
+            pass += 1;
+            let merged_path = path.with_extension(format!("spill{pass}"));
+            let mut writer = BufWriter::new(File::create(&merged_path)?);
+            let mut written = 0;
+            let mut merged_runs = Vec::with_capacity(runs.len().div_ceil(MAX_OPEN_RUNS));
+            for chunk in runs.chunks(MAX_OPEN_RUNS)
+            {
+                let mut run = Run { start: written, len: 0 };
+                for event in Self::open(&path, chunk)?
+                {
+                    written += write_event(&mut writer, &event?)?;
+                    run.len += 1;
+                }
+                merged_runs.push(run);
+            }
+            writer.flush()?;
+            std::fs::remove_file(&path)?;
+            (path, runs) = (merged_path, merged_runs);
+        }
+        let mut merge = Self::open(&path, &runs)?;
+        merge.path = Some(path);
+        Ok(merge)
+    }
+
+    fn open(path: &Path, runs: &[Run]) -> io::Result<Self>
+    {
+        let mut merge = Self::empty();
+        for run in runs
+        {
+            let mut reader = BufReader::new(File::open(path)?);
+            reader.seek(SeekFrom::Start(run.start))?;
+            merge.runs.push(RunReader { reader, remaining: run.len });
+            merge.heads.push(None);
+            merge.advance(merge.runs.len() - 1)?;
+        }
+        Ok(merge)
+    }
+
+    /// Reads the next event of `run` into its head
+    fn advance(&mut self, run: usize) -> io::Result<()>
+    {
+        if let Some(event) = self.runs[run].next()?
+        {
+            self.queue.push(Reverse((event.time, Reverse(event.end()), run)));
+            self.heads[run] = Some(event);
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Merge
+{
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        let Reverse((.., run)) = self.queue.pop()?;
+        let event = self.heads[run].take().expect("Queued runs have a head");
+        Some(self.advance(run).map(|()| event))
+    }
+}
+
+impl Drop for Merge
+{
+    fn drop(&mut self)
+    {
+        if let Some(path) = &self.path
+        {
+            // Readers have to be closed before removing on some platforms
+            self.runs.clear();
+            std::fs::remove_file(path).unwrap_or_else(|err| println!("Failed to remove {} - Reason: {err}", path.display()));
+        }
+    }
+}
+
+struct RunReader
+{
+    reader: BufReader<File>,
+    remaining: usize,
+}
+
+impl RunReader
+{
+    fn next(&mut self) -> io::Result<Option<Event>>
+    {
+        if self.remaining == 0
+        {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        Ok(Some(serde_json::from_str(&line)?))
+    }
+}
+
+/// Writes `event` as one json line, returns the number of bytes written
+pub(crate) fn write_event(writer: &mut impl Write, event: &Event) -> io::Result<u64>
+{
+    let mut line = serde_json::to_vec(event)?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    Ok(line.len() as u64)
+}
@@ -0,0 +1,119 @@
+use std::borrow::Cow;
+
+use scoper_base::{AsyncId, AsyncPhase, InstantScopeSize, TraceInfo, Value};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    TimePoint, json::print_tread_id, strings::Strings, types::{AsyncEvent, FlowEvent, FlowPhase, ObjectEvent, TaggedData, TaggedTrace}
+};
+
+pub(crate) type Text = Cow<'static, str>;
+
+/// A recorded event that owns its data, all writers work on these
+/// Times are nanoseconds since the start of the recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event
+{
+    pub name: Text,
+    pub category: Text,
+    pub header: Text,
+    /// Static args of the [`TraceInfo`]
+    pub static_args: Text,
+    pub args: Vec<(Text, Value)>,
+    /// Printed id of the recording thread
+    pub thread: u64,
+    /// Start of scopes, otherwise the moment the event happened
+    pub time: u64,
+    pub data: EventData,
+}
+
+/// Kind of an [`Event`] with the data only it has
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum EventData
+{
+    Scope
+    {
+        duration: u64
+    },
+    Counter(Value),
+    Instant(InstantScopeSize),
+    Async(AsyncPhase, AsyncId),
+    Flow(FlowPhase, u64),
+    Object(ObjectEvent),
+}
+
+impl Event
+{
+    /// End of scopes, otherwise the moment the event happened
+    #[must_use]
+    pub fn end(&self) -> u64
+    {
+        match self.data
+        {
+            EventData::Scope { duration } => self.time + duration,
+            _ => self.time,
+        }
+    }
+
+    /// Events end up ordered by start, enclosing scopes first
+    pub(crate) fn sort(events: &mut [Self]) { events.sort_by_key(|event| (event.time, std::cmp::Reverse(event.end()))); }
+
+    /// Moves the event `offset` nanoseconds earlier
+    /// Like when building it, it is dropped if it ended before the new zero
+    pub(crate) fn shift(mut self, offset: u64) -> Option<Self>
+    {
+        let end = self.end().checked_sub(offset)?;
+        self.time = self.time.saturating_sub(offset);
+        if let EventData::Scope { duration } = &mut self.data
+        {
+            *duration = end - self.time;
+        }
+        Some(self)
+    }
+}
+
+impl TaggedTrace
+{
+    /// Owned event relative to `zero`, `None` if it ended before that
+    pub(crate) fn into_event(self, zero: TimePoint, strings: &Strings) -> Option<Event>
+    {
+        fn nanos(duration: std::time::Duration) -> u64 { duration.as_nanos().try_into().unwrap_or(u64::MAX) }
+
+        let end = nanos(self.0.time_point.checked_duration_since(zero)?);
+        let (time, data) = match self.1
+        {
+            TaggedData::Scope(start) =>
+            {
+                // Viewer does not handle negative well
+                let start = nanos(start.0.saturating_duration_since(zero));
+                (start, EventData::Scope { duration: end - start })
+            },
+            TaggedData::Counter(value) => (end, EventData::Counter(value)),
+            TaggedData::Instant(scope_size) => (end, EventData::Instant(scope_size)),
+            TaggedData::Async(AsyncEvent(phase, id)) => (end, EventData::Async(phase, id)),
+            TaggedData::Flow(FlowEvent(phase, id)) => (end, EventData::Flow(phase, id)),
+            TaggedData::Object(event) => (end, EventData::Object(event)),
+        };
+
+        let &TraceInfo {
+            name,
+            category,
+            header,
+            args,
+        } = self.0.info;
+
+        // Ids are tagged with their session, scopes closed in another one are interned there again
+        let name = self.0.name.and_then(|id| strings.get(id)).map_or(Cow::Borrowed(name), |name| Cow::Owned(name.to_string()));
+
+        Some(Event {
+            name,
+            category: Cow::Borrowed(category),
+            header: Cow::Borrowed(header),
+            static_args: Cow::Borrowed(args),
+            args: self.0.args.into_iter().map(|(key, value)| (Cow::Borrowed(key), value)).collect(),
+            thread: print_tread_id(self.0.thread_id),
+            time,
+            data,
+        })
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+
+use crate::model::{Event, EventData};
+
+/// A scope placed in the call tree of its thread
+pub(crate) struct Span<'a>
+{
+    pub event: &'a Event,
+    /// Position of the event in the recorded events
+    pub index: usize,
+    pub parent: Option<usize>,
+    /// Clamped to the end of the parent, scopes recorded by hand might overlap it
+    pub end: u64,
+    /// Time spent in direct children
+    pub children: u64,
+}
+
+impl Span<'_>
+{
+    pub(crate) fn start(&self) -> u64 { self.event.time }
+
+    pub(crate) fn duration(&self) -> u64 { self.end - self.start() }
+
+    /// Time not spent in any of the children
+    pub(crate) fn self_time(&self) -> u64 { self.duration().saturating_sub(self.children) }
+}
+
+/// The scopes one thread recorded under one header, in order of their start
+pub(crate) struct ThreadScopes<'a>
+{
+    pub header: &'a str,
+    pub thread: u64,
+    pub spans: Vec<Span<'a>>,
+}
+
+/// Opening and closing of the spans, closes come at the end of the span
+pub(crate) enum Step
+{
+    Open(usize),
+    Close(usize),
+}
+
+impl ThreadScopes<'_>
+{
+    /// Indices of the spans from the root down to `index`
+    pub(crate) fn stack(&self, index: usize) -> Vec<usize>
+    {
+        let mut stack: Vec<_> = std::iter::successors(Some(index), |&index| self.spans[index].parent).collect();
+        stack.reverse();
+        stack
+    }
+
+    /// Every span is opened and closed again, properly nested
+    pub(crate) fn steps(&self) -> Vec<Step>
+    {
+        let mut steps = Vec::with_capacity(self.spans.len() * 2);
+        let mut open: Vec<usize> = Vec::new();
+        for (index, span) in self.spans.iter().enumerate()
+        {
+            while open.last().is_some_and(|&top| Some(top) != span.parent)
+            {
+                steps.push(Step::Close(open.pop().expect("Checked above")));
+            }
+            steps.push(Step::Open(index));
+            open.push(index);
+        }
+        steps.extend(open.into_iter().rev().map(Step::Close));
+        steps
+    }
+}
+
+/// Call trees of the scopes in `events`, which have to be ordered already
+/// Other events are skipped, threads are ordered by header and thread id
+pub(crate) fn threads(events: &[Event]) -> Vec<ThreadScopes<'_>> { nest(events, |event| (&event.header, event.thread)) }
+
+/// Like [`threads`], but the scopes of all headers of a thread share one call tree, named by the header of its first scope
+pub(crate) fn threads_across_headers(events: &[Event]) -> Vec<ThreadScopes<'_>> { nest(events, |event| ("", event.thread)) }
+
+fn nest<'a>(events: &'a [Event], key: impl Fn(&'a Event) -> (&'a str, u64)) -> Vec<ThreadScopes<'a>>
+{
+    let mut threads: BTreeMap<(&str, u64), ThreadScopes> = BTreeMap::new();
+    // Open spans of every thread
+    let mut stacks: BTreeMap<(&str, u64), Vec<usize>> = BTreeMap::new();
+    for (event_index, event) in events.iter().enumerate()
+    {
+        let EventData::Scope { .. } = event.data
+        else
+        {
+            continue;
+        };
+        let key = key(event);
+        let thread = threads.entry(key).or_insert_with(|| ThreadScopes {
+            header: &event.header,
+            thread: event.thread,
+            spans: Vec::new(),
+        });
+        let stack = stacks.entry(key).or_default();
+        while stack.last().is_some_and(|&top| thread.spans[top].end <= event.time)
+        {
+            stack.pop();
+        }
+        let parent = stack.last().copied();
+        let end = parent.map_or(event.end(), |parent| event.end().min(thread.spans[parent].end));
+        let index = thread.spans.len();
+        thread.spans.push(Span {
+            event,
+            index: event_index,
+            parent,
+            end,
+            children: 0,
+        });
+        if let Some(parent) = parent
+        {
+            thread.spans[parent].children += end - event.time;
+        }
+        stack.push(index);
+    }
+    threads.into_values().collect()
+}
@@ -0,0 +1,92 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use scoper_base::Info;
+use serde_json as json;
+
+use crate::{
+    session::{self, Active},
+    types::{BaseInfo, ObjectEvent, Trace},
+};
+
+static NEXT_OBJECT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Types that can be tracked with [`Traced`], usually implemented with `#[derive(TracedObject)]`
+pub trait TracedObject
+{
+    const INFO: Info;
+}
+
+/// Records the lifetime of the wrapped object
+/// Emits the created event on construction and the destroyed event when dropped
+pub struct Traced<T>
+{
+    value: T,
+    info: Info,
+    id: u64,
+    session: Option<Active>,
+}
+
+impl<T: TracedObject> Traced<T>
+{
+    #[must_use]
+    pub fn new(value: T) -> Self { Self::with_info(T::INFO, value) }
+}
+
+impl<T> Traced<T>
+{
+    #[must_use]
+    pub fn with_info(info: Info, value: T) -> Self
+    {
+        let traced = Self {
+            value,
+            info,
+            id: NEXT_OBJECT_ID.fetch_add(1, Ordering::Relaxed),
+            session: session::active(),
+        };
+        traced.push(ObjectEvent::Created(traced.id));
+        traced
+    }
+
+    /// Records the current state of the object
+    pub fn snapshot(&self)
+    where
+        T: serde::Serialize,
+    {
+        self.snapshot_with(&self.value);
+    }
+
+    /// Records an explicit state for the object
+    pub fn snapshot_with(&self, state: &impl serde::Serialize)
+    {
+        let state = json::to_value(state).unwrap_or_else(|err| json::Value::String(format!("Invalid snapshot - Reason: {err}")));
+        self.push(ObjectEvent::Snapshot(self.id, state));
+    }
+
+    fn push(&self, event: ObjectEvent)
+    {
+        if let Some(session) = &self.session
+        {
+            session.push(Trace(BaseInfo::build_now(self.info), event));
+        }
+    }
+}
+
+impl<T> Deref for Traced<T>
+{
+    type Target = T;
+
+    fn deref(&self) -> &T { &self.value }
+}
+
+impl<T> DerefMut for Traced<T>
+{
+    fn deref_mut(&mut self) -> &mut T { &mut self.value }
+}
+
+impl<T> Drop for Traced<T>
+{
+    fn drop(&mut self) { self.push(ObjectEvent::Destroyed(self.id)); }
+}
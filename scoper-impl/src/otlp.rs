@@ -0,0 +1,289 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use scoper_base::Value;
+use serde_json::{Value as JsonValue, json};
+
+use crate::{
+    json::json_args, model::{Event, EventData}, nesting::{self, ThreadScopes}, record_scope::MetaTrace
+};
+
+/// OTLP/JSON `resourceSpans` and `resourceMetrics` of `events`, the recording started at `start`
+/// Headers become resources and categories instrumentation scopes
+/// Scopes become spans nested like they were recorded, instants events of the innermost scope around them
+/// Counters become gauges, every arg of a counter its own gauge
+pub(crate) fn export(events: &[Event], meta_traces: &[MetaTrace], start: SystemTime) -> (JsonValue, JsonValue)
+{
+    let start = start.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos().try_into().unwrap_or(u64::MAX));
+    (traces(events, meta_traces, start), metrics(events, meta_traces, start))
+}
+
+/// Spans of every header and category
+type Grouped<'a> = BTreeMap<&'a str, BTreeMap<&'a str, Vec<JsonValue>>>;
+
+fn traces(events: &[Event], meta_traces: &[MetaTrace], start: u64) -> JsonValue
+{
+    let threads = nesting::threads(events);
+    let mut ids = Ids::new(start);
+    // Spans of every thread in the order of the scopes
+    let mut spans: Vec<Vec<JsonValue>> = Vec::with_capacity(threads.len());
+    for thread in &threads
+    {
+        let thread_name = MetaTrace::thread_name(meta_traces, thread.header, thread.thread);
+        // Trace and span id of every scope
+        let mut thread_ids: Vec<(String, String)> = Vec::with_capacity(thread.spans.len());
+        let mut jsons = Vec::with_capacity(thread.spans.len());
+        for span in &thread.spans
+        {
+            let (trace_id, parent_id) = match span.parent
+            {
+                Some(parent) => (thread_ids[parent].0.clone(), Some(thread_ids[parent].1.clone())),
+                None => (ids.trace(), None),
+            };
+            let span_id = ids.span();
+            let mut attributes = vec![attribute("thread.id", &json!(thread.thread)), attribute("thread.name", &json!(thread_name))];
+            attributes.extend(arguments(span.event));
+            let mut json = json!({
+                "traceId": trace_id,
+                "spanId": span_id,
+                "name": span.event.name,
+                "kind": 1,
+                "startTimeUnixNano": (start + span.start()).to_string(),
+                "endTimeUnixNano": (start + span.end).to_string(),
+                "attributes": attributes,
+                "events": [],
+            });
+            if let Some(parent_id) = parent_id
+            {
+                json["parentSpanId"] = json!(parent_id);
+            }
+            jsons.push(json);
+            thread_ids.push((trace_id, span_id));
+        }
+        spans.push(jsons);
+    }
+
+    let mut grouped = Grouped::new();
+    for event in events
+    {
+        let EventData::Instant(_) = event.data
+        else
+        {
+            continue;
+        };
+        let span_event = json!({
+            "timeUnixNano": (start + event.time).to_string(),
+            "name": event.name,
+            "attributes": arguments(event).collect::<Vec<_>>(),
+        });
+        let thread = threads.iter().position(|thread| thread.header == event.header && thread.thread == event.thread);
+        if let Some((thread, span)) = thread.and_then(|thread| Some((thread, enclosing(&threads[thread], event.time)?)))
+        {
+            spans[thread][span]["events"].as_array_mut().expect("Created above").push(span_event);
+        }
+        else
+        {
+            // Nothing to attach it to, it stands on its own
+            let thread_name = MetaTrace::thread_name(meta_traces, &event.header, event.thread);
+            let mut attributes = vec![attribute("thread.id", &json!(event.thread)), attribute("thread.name", &json!(thread_name))];
+            attributes.extend(arguments(event));
+            let span = json!({
+                "traceId": ids.trace(),
+                "spanId": ids.span(),
+                "name": event.name,
+                "kind": 1,
+                "startTimeUnixNano": (start + event.time).to_string(),
+                "endTimeUnixNano": (start + event.time).to_string(),
+                "attributes": attributes,
+                "events": [span_event],
+            });
+            grouped.entry(&event.header).or_default().entry(&event.category).or_default().push(span);
+        }
+    }
+
+    for (thread, spans) in threads.iter().zip(spans)
+    {
+        for (span, json) in thread.spans.iter().zip(spans)
+        {
+            grouped.entry(thread.header).or_default().entry(&span.event.category).or_default().push(json);
+        }
+    }
+    let resource_spans: Vec<_> = grouped
+        .into_iter()
+        .map(|(header, scopes)| {
+            let scope_spans: Vec<_> = scopes.into_iter().map(|(category, spans)| json!({"scope": scope(category), "spans": spans})).collect();
+            json!({"resource": resource(meta_traces, header), "scopeSpans": scope_spans})
+        })
+        .collect();
+    json!({ "resourceSpans": resource_spans })
+}
+
+fn metrics(events: &[Event], meta_traces: &[MetaTrace], start: u64) -> JsonValue
+{
+    // Data points of every gauge, grouped by header and category
+    let mut gauges: BTreeMap<&str, BTreeMap<&str, BTreeMap<String, Vec<JsonValue>>>> = BTreeMap::new();
+    for event in events
+    {
+        let EventData::Counter(value) = &event.data
+        else
+        {
+            continue;
+        };
+        let series = std::iter::once((event.name.to_string(), value))
+            .chain(event.args.iter().map(|(key, value)| (format!("{}.{key}", event.name), value)));
+        for (name, value) in series
+        {
+            let mut point = json!({
+                "timeUnixNano": (start + event.time).to_string(),
+                "attributes": [attribute("thread.id", &json!(event.thread))],
+            });
+            match *value
+            {
+                Value::UInt(uint) => point["asInt"] = json!(uint.to_string()),
+                Value::IInt(iint) => point["asInt"] = json!(iint.to_string()),
+                Value::Float(float) => point["asDouble"] = json!(float),
+                Value::Bool(bool) => point["asInt"] = json!(u8::from(bool).to_string()),
+                Value::Str(_) => continue,
+            }
+            gauges.entry(&event.header).or_default().entry(&event.category).or_default().entry(name).or_default().push(point);
+        }
+    }
+    let resource_metrics: Vec<_> = gauges
+        .into_iter()
+        .map(|(header, scopes)| {
+            let scope_metrics: Vec<_> = scopes
+                .into_iter()
+                .map(|(category, gauges)| {
+                    let metrics: Vec<_> = gauges
+                        .into_iter()
+                        .map(|(name, points)| json!({"name": name, "gauge": {"dataPoints": points}}))
+                        .collect();
+                    json!({"scope": scope(category), "metrics": metrics})
+                })
+                .collect();
+            json!({"resource": resource(meta_traces, header), "scopeMetrics": scope_metrics})
+        })
+        .collect();
+    json!({ "resourceMetrics": resource_metrics })
+}
+
+/// Index of the innermost span of `thread` that is open at `time`
+fn enclosing(thread: &ThreadScopes, time: u64) -> Option<usize>
+{
+    // Spans enclosing `time` are ancestors of the last one that started before it
+    let last = thread.spans.partition_point(|span| span.start() <= time).checked_sub(1)?;
+    std::iter::successors(Some(last), |&index| thread.spans[index].parent).find(|&index| thread.spans[index].end > time)
+}
+
+/// The macros separate the module path by commas to split it into trace categories
+fn scope(category: &str) -> JsonValue { json!({ "name": category.replace(',', "::") }) }
+
+fn resource(meta_traces: &[MetaTrace], header: &str) -> JsonValue
+{
+    json!({"attributes": [
+        attribute("service.name", &json!(MetaTrace::process_name(meta_traces, header))),
+        attribute("scoper.header", &json!(header)),
+    ]})
+}
+
+fn arguments(event: &Event) -> impl Iterator<Item = JsonValue> + use<>
+{
+    let JsonValue::Object(args) = json_args(&event.static_args, &event.args)
+    else
+    {
+        unreachable!("Args are always an object")
+    };
+    args.into_iter().map(|(key, value)| attribute(&key, &value))
+}
+
+fn attribute(key: &str, value: &JsonValue) -> JsonValue
+{
+    let value = match value
+    {
+        JsonValue::Bool(bool) => json!({ "boolValue": bool }),
+        JsonValue::Number(number) if number.is_f64() => json!({ "doubleValue": number }),
+        // Protobuf json keeps 64 bit integers in strings
+        JsonValue::Number(number) => json!({ "intValue": number.to_string() }),
+        JsonValue::String(string) => json!({ "stringValue": string }),
+        other => json!({ "stringValue": other.to_string() }),
+    };
+    json!({"key": key, "value": value})
+}
+
+/// Trace and span ids, every root scope starts its own trace
+struct Ids
+{
+    /// Keeps the traces of different recordings apart
+    seed: u64,
+    traces: u64,
+    spans: u64,
+}
+
+impl Ids
+{
+    fn new(seed: u64) -> Self { Self { seed, traces: 0, spans: 0 } }
+
+    fn trace(&mut self) -> String
+    {
+        self.traces += 1;
+        format!("{:016x}{:016x}", self.seed, self.traces)
+    }
+
+    fn span(&mut self) -> String
+    {
+        self.spans += 1;
+        format!("{:016x}", self.spans)
+    }
+}
+
+/// Sends the export to the OTLP/HTTP receiver at `endpoint`, like `http://localhost:4318`
+pub(crate) fn send(endpoint: &str, (traces, metrics): &(JsonValue, JsonValue)) -> io::Result<()>
+{
+    if !traces["resourceSpans"].as_array().is_some_and(Vec::is_empty)
+    {
+        post(endpoint, "/v1/traces", &traces.to_string())?;
+    }
+    if !metrics["resourceMetrics"].as_array().is_some_and(Vec::is_empty)
+    {
+        post(endpoint, "/v1/metrics", &metrics.to_string())?;
+    }
+    Ok(())
+}
+
+/// Minimal HTTP/1.1 POST, only plain http is supported
+fn post(endpoint: &str, path: &str, body: &str) -> io::Result<()>
+{
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{message}: {endpoint}"));
+    let rest = endpoint.strip_prefix("http://").ok_or_else(|| invalid("Only http endpoints are supported"))?;
+    let (host, base) = rest.split_once('/').map_or((rest, ""), |(host, base)| (host, base.trim_end_matches('/')));
+    if host.is_empty()
+    {
+        return Err(invalid("Endpoint without host"));
+    }
+    let address = if host.contains(':') { host.to_string() } else { format!("{host}:4318") };
+    let path = if base.is_empty() { path.to_string() } else { format!("/{base}{path}") };
+
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    write!(
+        stream,
+        "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    match status.split_whitespace().nth(1)
+    {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!("OTLP receiver at {endpoint} answered {}", status.trim_end()))),
+    }
+}
+
+/// Json lines like the file exporter of the collector writes them, traces first
+pub(crate) fn lines((traces, metrics): &(JsonValue, JsonValue)) -> String { format!("{traces}\n{metrics}\n") }
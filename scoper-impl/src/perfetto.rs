@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+
+use prost::Message;
+use scoper_base::{AsyncPhase, InstantScopeSize, Value};
+
+use crate::{
+    model::{Event, EventData, Text}, record_scope::MetaTrace, types::{FlowPhase, ObjectEvent}
+};
+
+/// Subset of the perfetto `TracePacket` protos, the tags follow `perfetto/protos/perfetto/trace`
+mod proto
+{
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Trace
+    {
+        #[prost(message, repeated, tag = "1")]
+        pub packet: Vec<TracePacket>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TracePacket
+    {
+        #[prost(uint64, optional, tag = "8")]
+        pub timestamp: Option<u64>,
+        #[prost(uint32, optional, tag = "10")]
+        pub trusted_packet_sequence_id: Option<u32>,
+        #[prost(message, optional, tag = "11")]
+        pub track_event: Option<TrackEvent>,
+        #[prost(message, optional, tag = "12")]
+        pub interned_data: Option<InternedData>,
+        #[prost(uint32, optional, tag = "13")]
+        pub sequence_flags: Option<u32>,
+        #[prost(message, optional, tag = "60")]
+        pub track_descriptor: Option<TrackDescriptor>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TrackDescriptor
+    {
+        #[prost(uint64, optional, tag = "1")]
+        pub uuid: Option<u64>,
+        #[prost(string, optional, tag = "2")]
+        pub name: Option<String>,
+        #[prost(message, optional, tag = "3")]
+        pub process: Option<ProcessDescriptor>,
+        #[prost(uint64, optional, tag = "5")]
+        pub parent_uuid: Option<u64>,
+        #[prost(message, optional, tag = "8")]
+        pub counter: Option<CounterDescriptor>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ProcessDescriptor
+    {
+        #[prost(int32, optional, tag = "1")]
+        pub pid: Option<i32>,
+        #[prost(string, optional, tag = "6")]
+        pub process_name: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CounterDescriptor {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TrackEvent
+    {
+        #[prost(uint64, repeated, packed = "false", tag = "3")]
+        pub category_iids: Vec<u64>,
+        #[prost(message, repeated, tag = "4")]
+        pub debug_annotations: Vec<DebugAnnotation>,
+        #[prost(enumeration = "Type", optional, tag = "9")]
+        pub r#type: Option<i32>,
+        #[prost(uint64, optional, tag = "10")]
+        pub name_iid: Option<u64>,
+        #[prost(uint64, optional, tag = "11")]
+        pub track_uuid: Option<u64>,
+        #[prost(int64, optional, tag = "30")]
+        pub counter_value: Option<i64>,
+        #[prost(double, optional, tag = "44")]
+        pub double_counter_value: Option<f64>,
+        #[prost(fixed64, repeated, packed = "false", tag = "47")]
+        pub flow_ids: Vec<u64>,
+        #[prost(fixed64, repeated, packed = "false", tag = "48")]
+        pub terminating_flow_ids: Vec<u64>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Type
+    {
+        SliceBegin = 1,
+        SliceEnd = 2,
+        Instant = 3,
+        Counter = 4,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DebugAnnotation
+    {
+        #[prost(bool, optional, tag = "2")]
+        pub bool_value: Option<bool>,
+        #[prost(uint64, optional, tag = "3")]
+        pub uint_value: Option<u64>,
+        #[prost(int64, optional, tag = "4")]
+        pub int_value: Option<i64>,
+        #[prost(double, optional, tag = "5")]
+        pub double_value: Option<f64>,
+        #[prost(string, optional, tag = "6")]
+        pub string_value: Option<String>,
+        #[prost(string, optional, tag = "9")]
+        pub legacy_json_value: Option<String>,
+        #[prost(string, optional, tag = "10")]
+        pub name: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct InternedData
+    {
+        #[prost(message, repeated, tag = "1")]
+        pub event_categories: Vec<InternedString>,
+        #[prost(message, repeated, tag = "2")]
+        pub event_names: Vec<InternedString>,
+    }
+
+    /// `EventCategory` and `EventName` share their layout
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct InternedString
+    {
+        #[prost(uint64, optional, tag = "1")]
+        pub iid: Option<u64>,
+        #[prost(string, optional, tag = "2")]
+        pub name: Option<String>,
+    }
+}
+
+use proto::{DebugAnnotation, TrackEvent, Type};
+
+const SEQUENCE_ID: u32 = 1;
+const SEQ_INCREMENTAL_STATE_CLEARED: u32 = 1;
+const SEQ_NEEDS_INCREMENTAL_STATE: u32 = 2;
+
+/// Encodes `events` as a perfetto trace
+/// Headers become processes, every thread gets a track below each header it recorded into
+pub(crate) fn encode(events: &[Event], meta_traces: &[MetaTrace]) -> Vec<u8>
+{
+    let mut writer = Writer::new(meta_traces);
+    for event in events
+    {
+        writer.add(event);
+    }
+    writer.finish().encode_to_vec()
+}
+
+#[derive(Hash, PartialEq, Eq)]
+enum TrackKey
+{
+    Global,
+    Process(Text),
+    Thread(Text, u64),
+    Counter(Text, Text),
+    Async(Text, Text, u64),
+    Object(Text, Text, u64),
+}
+
+struct Writer<'a>
+{
+    meta_traces: &'a [MetaTrace],
+    tracks: HashMap<TrackKey, u64>,
+    descriptors: Vec<proto::TracePacket>,
+    names: HashMap<Text, u64>,
+    categories: HashMap<Text, u64>,
+    /// Ends of the open scopes of every track
+    open: HashMap<u64, Vec<u64>>,
+    events: Vec<(u64, proto::TracePacket)>,
+}
+
+impl<'a> Writer<'a>
+{
+    fn new(meta_traces: &'a [MetaTrace]) -> Self
+    {
+        Self {
+            meta_traces,
+            tracks: HashMap::new(),
+            descriptors: Vec::new(),
+            names: HashMap::new(),
+            categories: HashMap::new(),
+            open: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, event: &Event)
+    {
+        let thread = self.track(TrackKey::Thread(event.header.clone(), event.thread));
+        match &event.data
+        {
+            EventData::Scope { duration } =>
+            {
+                self.close_scopes(thread, event.time, true);
+                self.push(event, thread, event.time, Self::named(Type::SliceBegin, event));
+                self.open.entry(thread).or_default().push(event.time + duration);
+            },
+            EventData::Counter(value) =>
+            {
+                let mut series = vec![(event.name.clone(), value)];
+                series.extend(event.args.iter().map(|(key, value)| (Text::Owned(format!("{} {key}", event.name)), value)));
+                for (name, value) in series
+                {
+                    let track = self.track(TrackKey::Counter(event.header.clone(), name));
+                    let mut counter = TrackEvent {
+                        r#type: Some(Type::Counter.into()),
+                        ..TrackEvent::default()
+                    };
+                    match *value
+                    {
+                        Value::UInt(uint) => counter.counter_value = Some(i64::try_from(uint).unwrap_or(i64::MAX)),
+                        Value::IInt(iint) => counter.counter_value = Some(iint),
+                        Value::Float(float) => counter.double_counter_value = Some(float),
+                        Value::Bool(bool) => counter.counter_value = Some(bool.into()),
+                        Value::Str(_) => continue,
+                    }
+                    self.push(event, track, event.time, counter);
+                }
+            },
+            EventData::Instant(scope_size) =>
+            {
+                let track = match scope_size
+                {
+                    InstantScopeSize::Thread => thread,
+                    InstantScopeSize::Process => self.track(TrackKey::Process(event.header.clone())),
+                    InstantScopeSize::Global => self.track(TrackKey::Global),
+                };
+                self.close_scopes(track, event.time, false);
+                self.push(event, track, event.time, Self::named(Type::Instant, event));
+            },
+            EventData::Async(phase, id) =>
+            {
+                let track = self.track(TrackKey::Async(event.header.clone(), event.category.clone(), async_id(*id)));
+                let track_event = match phase
+                {
+                    AsyncPhase::Begin => Self::named(Type::SliceBegin, event),
+                    AsyncPhase::Step => Self::named(Type::Instant, event),
+                    AsyncPhase::End => Self::unnamed(Type::SliceEnd),
+                };
+                self.push(event, track, event.time, track_event);
+            },
+            EventData::Flow(phase, id) =>
+            {
+                self.close_scopes(thread, event.time, false);
+                let mut track_event = Self::named(Type::Instant, event);
+                match phase
+                {
+                    FlowPhase::Out | FlowPhase::Step => track_event.flow_ids.push(*id),
+                    FlowPhase::In => track_event.terminating_flow_ids.push(*id),
+                }
+                self.push(event, thread, event.time, track_event);
+            },
+            EventData::Object(object) =>
+            {
+                let (ObjectEvent::Created(id) | ObjectEvent::Snapshot(id, _) | ObjectEvent::Destroyed(id)) = object;
+                let track = self.track(TrackKey::Object(event.header.clone(), event.name.clone(), *id));
+                let track_event = match object
+                {
+                    ObjectEvent::Created(_) => Self::named(Type::SliceBegin, event),
+                    ObjectEvent::Snapshot(_, snapshot) =>
+                    {
+                        let mut track_event = Self::named(Type::Instant, event);
+                        track_event.debug_annotations.push(DebugAnnotation {
+                            name: Some("snapshot".to_string()),
+                            legacy_json_value: Some(snapshot.to_string()),
+                            ..DebugAnnotation::default()
+                        });
+                        track_event
+                    },
+                    ObjectEvent::Destroyed(_) => Self::unnamed(Type::SliceEnd),
+                };
+                self.push(event, track, event.time, track_event);
+            },
+        }
+    }
+
+    /// Ends the scopes of `track` that are over at `time`
+    /// Scopes ending exactly at `time` are only closed before a new scope
+    fn close_scopes(&mut self, track: u64, time: u64, inclusive: bool)
+    {
+        let Some(open) = self.open.get_mut(&track) else { return };
+        let mut ends = Vec::new();
+        while let Some(&end) = open.last()
+            && (end < time || inclusive && end == time)
+        {
+            open.pop();
+            ends.push(end);
+        }
+        for end in ends
+        {
+            self.events.push((end, Self::packet(end, track, Self::unnamed(Type::SliceEnd))));
+        }
+    }
+
+    fn unnamed(r#type: Type) -> TrackEvent
+    {
+        TrackEvent {
+            r#type: Some(r#type.into()),
+            ..TrackEvent::default()
+        }
+    }
+
+    fn named(r#type: Type, event: &Event) -> TrackEvent
+    {
+        let mut track_event = Self::unnamed(r#type);
+        track_event.debug_annotations = annotations(event);
+        track_event
+    }
+
+    fn push(&mut self, event: &Event, track: u64, time: u64, mut track_event: TrackEvent)
+    {
+        let mut interned = proto::InternedData::default();
+        if track_event.r#type != Some(Type::SliceEnd.into()) && track_event.r#type != Some(Type::Counter.into())
+        {
+            let (iid, new) = intern(&mut self.names, &event.name);
+            track_event.name_iid = Some(iid);
+            if new
+            {
+                interned.event_names.push(proto::InternedString {
+                    iid: Some(iid),
+                    name: Some(event.name.to_string()),
+                });
+            }
+            let (iid, new) = intern(&mut self.categories, &event.category);
+            track_event.category_iids.push(iid);
+            if new
+            {
+                interned.event_categories.push(proto::InternedString {
+                    iid: Some(iid),
+                    name: Some(event.category.to_string()),
+                });
+            }
+        }
+        let mut packet = Self::packet(time, track, track_event);
+        if interned != proto::InternedData::default()
+        {
+            packet.interned_data = Some(interned);
+        }
+        self.events.push((time, packet));
+    }
+
+    fn packet(time: u64, track: u64, mut track_event: TrackEvent) -> proto::TracePacket
+    {
+        track_event.track_uuid = Some(track);
+        proto::TracePacket {
+            timestamp: Some(time),
+            trusted_packet_sequence_id: Some(SEQUENCE_ID),
+            track_event: Some(track_event),
+            sequence_flags: Some(SEQ_NEEDS_INCREMENTAL_STATE),
+            ..proto::TracePacket::default()
+        }
+    }
+
+    /// Uuid of the track, its descriptor is emitted on first use
+    fn track(&mut self, key: TrackKey) -> u64
+    {
+        if let Some(&uuid) = self.tracks.get(&key)
+        {
+            return uuid;
+        }
+        // The process track has to exist before the uuid of its child is taken
+        let parent_uuid = match &key
+        {
+            TrackKey::Global | TrackKey::Process(_) => None,
+            TrackKey::Thread(header, _) | TrackKey::Counter(header, _) | TrackKey::Async(header, ..) | TrackKey::Object(header, ..) =>
+            {
+                Some(self.track(TrackKey::Process(header.clone())))
+            },
+        };
+        let uuid = self.tracks.len() as u64 + 1;
+        let mut descriptor = proto::TrackDescriptor {
+            uuid: Some(uuid),
+            parent_uuid,
+            ..proto::TrackDescriptor::default()
+        };
+        match &key
+        {
+            TrackKey::Global => descriptor.name = Some("Global".to_string()),
+            TrackKey::Process(header) =>
+            {
+                descriptor.process = Some(proto::ProcessDescriptor {
+                    pid: Some(i32::try_from(uuid).unwrap_or(i32::MAX)),
+                    process_name: Some(MetaTrace::process_name(self.meta_traces, header).to_string()),
+                });
+            },
+            TrackKey::Thread(header, thread) => descriptor.name = Some(MetaTrace::thread_name(self.meta_traces, header, *thread)),
+            TrackKey::Counter(_, name) =>
+            {
+                descriptor.name = Some(name.to_string());
+                descriptor.counter = Some(proto::CounterDescriptor {});
+            },
+            TrackKey::Async(_, category, id) => descriptor.name = Some(format!("{category} {id:#x}")),
+            TrackKey::Object(_, name, id) => descriptor.name = Some(format!("{name} {id:#x}")),
+        }
+        self.tracks.insert(key, uuid);
+        self.descriptors.push(proto::TracePacket {
+            trusted_packet_sequence_id: Some(SEQUENCE_ID),
+            track_descriptor: Some(descriptor),
+            ..proto::TracePacket::default()
+        });
+        uuid
+    }
+
+    fn finish(mut self) -> proto::Trace
+    {
+        let tracks: Vec<_> = self.open.keys().copied().collect();
+        for track in tracks
+        {
+            self.close_scopes(track, u64::MAX, true);
+        }
+        // Every track is ordered already, ends of enclosing scopes were emitted late
+        self.events.sort_by_key(|(time, _)| *time);
+
+        let clear = proto::TracePacket {
+            trusted_packet_sequence_id: Some(SEQUENCE_ID),
+            sequence_flags: Some(SEQ_INCREMENTAL_STATE_CLEARED),
+            ..proto::TracePacket::default()
+        };
+        proto::Trace {
+            packet: std::iter::once(clear).chain(self.descriptors).chain(self.events.into_iter().map(|(_, packet)| packet)).collect(),
+        }
+    }
+}
+
+fn intern(table: &mut HashMap<Text, u64>, text: &Text) -> (u64, bool)
+{
+    if let Some(&iid) = table.get(text)
+    {
+        return (iid, false);
+    }
+    let iid = table.len() as u64 + 1;
+    table.insert(text.clone(), iid);
+    (iid, true)
+}
+
+fn async_id(id: scoper_base::AsyncId) -> u64
+{
+    let (scoper_base::AsyncId::Id(id) | scoper_base::AsyncId::Local(id) | scoper_base::AsyncId::Global(id)) = id;
+    id
+}
+
+/// Static args that are a json object are split into their fields like in the chrome output
+fn annotations(event: &Event) -> Vec<DebugAnnotation>
+{
+    let mut annotations = Vec::new();
+    if !event.static_args.is_empty()
+    {
+        if let Ok(serde_json::Value::Object(map)) = serde_json::from_str(&event.static_args)
+        {
+            annotations.extend(map.into_iter().map(|(name, value)| DebugAnnotation {
+                name: Some(name),
+                legacy_json_value: Some(value.to_string()),
+                ..DebugAnnotation::default()
+            }));
+        }
+        else
+        {
+            annotations.push(DebugAnnotation {
+                name: Some("args".to_string()),
+                string_value: Some(event.static_args.to_string()),
+                ..DebugAnnotation::default()
+            });
+        }
+    }
+    annotations.extend(event.args.iter().map(|(name, value)| {
+        let mut annotation = DebugAnnotation {
+            name: Some(name.to_string()),
+            ..DebugAnnotation::default()
+        };
+        match value
+        {
+            Value::UInt(uint) => annotation.uint_value = Some(*uint),
+            Value::IInt(iint) => annotation.int_value = Some(*iint),
+            Value::Float(float) => annotation.double_value = Some(*float),
+            Value::Bool(bool) => annotation.bool_value = Some(*bool),
+            Value::Str(str) => annotation.string_value = Some(str.clone()),
+        }
+        annotation
+    }));
+    annotations
+}
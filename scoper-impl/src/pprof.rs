@@ -0,0 +1,222 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use prost::Message;
+
+use crate::{
+    model::{Event, Text}, nesting, record_scope::MetaTrace
+};
+
+/// Subset of `perftools.profiles.Profile`, the tags follow `profile.proto` of pprof
+mod proto
+{
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Profile
+    {
+        #[prost(message, repeated, tag = "1")]
+        pub sample_type: Vec<ValueType>,
+        #[prost(message, repeated, tag = "2")]
+        pub sample: Vec<Sample>,
+        #[prost(message, repeated, tag = "4")]
+        pub location: Vec<Location>,
+        #[prost(message, repeated, tag = "5")]
+        pub function: Vec<Function>,
+        #[prost(string, repeated, tag = "6")]
+        pub string_table: Vec<String>,
+        #[prost(int64, tag = "9")]
+        pub time_nanos: i64,
+        #[prost(int64, tag = "10")]
+        pub duration_nanos: i64,
+        #[prost(message, optional, tag = "11")]
+        pub period_type: Option<ValueType>,
+        #[prost(int64, tag = "12")]
+        pub period: i64,
+        #[prost(int64, tag = "14")]
+        pub default_sample_type: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ValueType
+    {
+        #[prost(int64, tag = "1")]
+        pub r#type: i64,
+        #[prost(int64, tag = "2")]
+        pub unit: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Sample
+    {
+        /// Leaf first
+        #[prost(uint64, repeated, tag = "1")]
+        pub location_id: Vec<u64>,
+        #[prost(int64, repeated, tag = "2")]
+        pub value: Vec<i64>,
+        #[prost(message, repeated, tag = "3")]
+        pub label: Vec<Label>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Label
+    {
+        #[prost(int64, tag = "1")]
+        pub key: i64,
+        #[prost(int64, tag = "2")]
+        pub str: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Location
+    {
+        #[prost(uint64, tag = "1")]
+        pub id: u64,
+        #[prost(message, repeated, tag = "4")]
+        pub line: Vec<Line>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Line
+    {
+        #[prost(uint64, tag = "1")]
+        pub function_id: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Function
+    {
+        #[prost(uint64, tag = "1")]
+        pub id: u64,
+        #[prost(int64, tag = "2")]
+        pub name: i64,
+        #[prost(int64, tag = "3")]
+        pub system_name: i64,
+        #[prost(int64, tag = "4")]
+        pub filename: i64,
+    }
+}
+
+/// Encodes the scopes in `events` as an uncompressed pprof profile starting at `start`
+/// Every stack of a thread is one sample with its inclusive and exclusive wall time in nanoseconds
+/// Each name and category is one function and location, threads and headers are labels of the samples
+pub(crate) fn encode(events: &[Event], meta_traces: &[MetaTrace], start: SystemTime) -> Vec<u8>
+{
+    let mut writer = Writer::default();
+    let sample_type = vec![writer.value_type("wall_inclusive", "nanoseconds"), writer.value_type("wall_exclusive", "nanoseconds")];
+    let (header_key, thread_key) = (writer.string("header"), writer.string("thread"));
+
+    // Equal stacks of a thread are summed up, ordered to keep the output stable
+    let mut samples: BTreeMap<(Vec<u64>, i64, i64), [u64; 2]> = BTreeMap::new();
+    let mut duration = 0;
+    for thread in nesting::threads(events)
+    {
+        let header = writer.string(MetaTrace::process_name(meta_traces, thread.header));
+        let thread_name = writer.string(&MetaTrace::thread_name(meta_traces, thread.header, thread.thread));
+        for (index, span) in thread.spans.iter().enumerate()
+        {
+            let mut stack: Vec<_> = thread.stack(index).into_iter().map(|index| writer.location(thread.spans[index].event)).collect();
+            stack.reverse();
+            let values = samples.entry((stack, header, thread_name)).or_default();
+            values[0] += span.duration();
+            values[1] += span.self_time();
+            duration = duration.max(span.end);
+        }
+    }
+
+    let period_type = writer.value_type("wall", "nanoseconds");
+    let default_sample_type = writer.string("wall_exclusive");
+    let profile = proto::Profile {
+        sample_type,
+        sample: samples
+            .into_iter()
+            .map(|((location_id, header, thread), values)| proto::Sample {
+                location_id,
+                value: values.into_iter().map(nanos).collect(),
+                label: vec![proto::Label { key: header_key, str: header }, proto::Label { key: thread_key, str: thread }],
+            })
+            .collect(),
+        location: writer.locations,
+        function: writer.functions,
+        string_table: writer.strings,
+        time_nanos: start.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos().try_into().unwrap_or(i64::MAX)),
+        duration_nanos: nanos(duration),
+        period_type: Some(period_type),
+        period: 1,
+        default_sample_type,
+    };
+    profile.encode_to_vec()
+}
+
+fn nanos(nanos: u64) -> i64 { nanos.try_into().unwrap_or(i64::MAX) }
+
+struct Writer
+{
+    /// The first string has to be empty
+    strings: Vec<String>,
+    string_ids: HashMap<String, i64>,
+    functions: Vec<proto::Function>,
+    locations: Vec<proto::Location>,
+    location_ids: HashMap<(Text, Text), u64>,
+}
+
+impl Default for Writer
+{
+    fn default() -> Self
+    {
+        Self {
+            strings: vec![String::new()],
+            string_ids: HashMap::from([(String::new(), 0)]),
+            functions: Vec::new(),
+            locations: Vec::new(),
+            location_ids: HashMap::new(),
+        }
+    }
+}
+
+impl Writer
+{
+    fn string(&mut self, string: &str) -> i64
+    {
+        if let Some(&id) = self.string_ids.get(string)
+        {
+            return id;
+        }
+        let id = i64::try_from(self.strings.len()).unwrap_or(i64::MAX);
+        self.strings.push(string.to_string());
+        self.string_ids.insert(string.to_string(), id);
+        id
+    }
+
+    fn value_type(&mut self, r#type: &str, unit: &str) -> proto::ValueType
+    {
+        proto::ValueType {
+            r#type: self.string(r#type),
+            unit: self.string(unit),
+        }
+    }
+
+    /// Functions and locations share their ids, both start at 1
+    fn location(&mut self, event: &Event) -> u64
+    {
+        if let Some(&id) = self.location_ids.get(&(event.name.clone(), event.category.clone()))
+        {
+            return id;
+        }
+        let id = self.locations.len() as u64 + 1;
+        let name = self.string(&event.name);
+        let filename = self.string(&event.category);
+        self.functions.push(proto::Function {
+            id,
+            name,
+            system_name: name,
+            filename,
+        });
+        self.locations.push(proto::Location {
+            id,
+            line: vec![proto::Line { function_id: id }],
+        });
+        self.location_ids.insert((event.name.clone(), event.category.clone()), id);
+        id
+    }
+}
@@ -1,54 +1,219 @@
 use std::{
-    fs::File,
-    io::BufWriter,
     path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
     thread::ThreadId,
+    time::SystemTime,
 };
 
 use serde_json as json;
 
-use crate::TimePoint;
+use crate::{
+    TimePoint, compression::Compression, format::Format, json::print_tread_id, limits::{BufferLimits, OverflowPolicy}, model::{Event, Text}, session::{self, Session, SessionHandle}, sink::{FileSink, OtlpSink, RecordingInfo, TraceSink}
+};
 
 pub struct RecordScope
 {
-    pub(crate) path: PathBuf,
+    /// File given to [`RecordScope::start`], its format and compression can still be changed
+    pub(crate) file: Option<FileSink>,
+    /// Further destinations of the same events
+    pub(crate) sinks: Vec<Box<dyn TraceSink>>,
+    /// Events are merged from the spill file instead of collected in memory
+    pub(crate) streaming: bool,
     pub(crate) record_start: TimePoint,
-    pub(crate) meta_data: json::Map<String, json::Value>,
-    pub(crate) meta_traces: Vec<MetaTrace>,
+    pub(crate) session: Arc<Session>,
 }
 
+/// Events handed to the sinks at once
+const BATCH: usize = 4096;
+
 impl RecordScope
 {
-    pub fn start(path: impl AsRef<Path>) -> Self
+    /// Starts a recording that collects the events of the current thread
+    /// Other threads can join it with [`RecordScope::session`]
+    pub fn start(path: impl AsRef<Path>) -> Self { Self::start_with_limits(path, BufferLimits::UNBOUNDED) }
+
+    /// Starts a recording whose buffers are bounded by `limits`
+    /// Spilled events are kept next to the output until it is written
+    pub fn start_with_limits(path: impl AsRef<Path>, limits: BufferLimits) -> Self
+    {
+        let file = FileSink::new(path);
+        let mut record = Self::new(limits, file.path.with_extension("spill"));
+        record.file = Some(file);
+        record
+    }
+
+    /// Starts a recording that hands its events to `sink` when dropped
+    /// Add more with [`RecordScope::add_sink`]
+    pub fn with_sink(sink: impl TraceSink + 'static) -> Self { Self::with_sink_and_limits(sink, BufferLimits::UNBOUNDED) }
+
+    /// Starts a recording for `sink` whose buffers are bounded by `limits`
+    /// Spilled events are kept in the temporary directory until it is written
+    pub fn with_sink_and_limits(sink: impl TraceSink + 'static, limits: BufferLimits) -> Self
+    {
+        static NEXT_SPILL: AtomicU64 = AtomicU64::new(0);
+        let spill = format!("scoper-{}-{}.spill", std::process::id(), NEXT_SPILL.fetch_add(1, Ordering::Relaxed));
+        let mut record = Self::new(limits, std::env::temp_dir().join(spill));
+        record.add_sink(sink);
+        record
+    }
+
+    fn new(limits: BufferLimits, spill_path: PathBuf) -> Self
     {
+        let session = Session::new(limits, spill_path);
+        session::activate(&session);
         Self {
-            path: path.as_ref().with_extension("json"),
-            record_start: TimePoint::now(),
-            meta_data: json::Map::default(),
-            meta_traces: Vec::default(),
+            file: None,
+            sinks: Vec::new(),
+            streaming: false,
+            record_start: session.epoch,
+            session,
             //section: "",
         }
     }
 
+    /// Starts a recording that keeps at most `chunk` events in memory
+    /// Full buffers are spilled next to the output, the sinks get them merged in batches when dropped
+    /// Json files are written while merging, other formats and sinks that gather events still hold all of them
+    pub fn start_streaming(path: impl AsRef<Path>, chunk: usize) -> Self
+    {
+        let mut record = Self::start_with_limits(path, BufferLimits::new(chunk, OverflowPolicy::FlushToDisk));
+        record.streaming = true;
+        record
+    }
+
+    /// Handle to enter this recording from other threads
+    #[must_use]
+    pub fn session(&self) -> SessionHandle { SessionHandle(self.session.clone()) }
+
+    /// Moves the events buffered so far into the spill file
+    ///
+    /// # Errors
+    /// Returns an Error if the spill file can not be written
+    pub fn flush_to_disk(&self) -> std::io::Result<()> { self.session.spill_all() }
+
+    /// Overrides the format picked by the extension, the path stays as it is
+    /// Only affects the file given to [`RecordScope::start`]
+    pub fn set_format(&mut self, format: Format)
+    {
+        if let Some(file) = &mut self.file
+        {
+            file.format = format;
+        }
+    }
+
+    /// Overrides the compression picked by the extension, the path stays as it is
+    /// Only affects the file given to [`RecordScope::start`]
+    pub fn set_compression(&mut self, compression: Compression)
+    {
+        if let Some(file) = &mut self.file
+        {
+            file.compression = compression;
+        }
+    }
+
+    /// Hands the events to `sink` as well when dropped
+    pub fn add_sink(&mut self, sink: impl TraceSink + 'static) { self.sinks.push(Box::new(sink)); }
+
+    /// Writes the recording to `path` as well, in the format and compression picked by its extension
+    /// E.g. a `trace.svg` flamegraph next to the `trace.json`
+    pub fn add_output(&mut self, path: impl AsRef<Path>) { self.add_sink(FileSink::new(path)); }
+
+    /// Sends the spans and gauges to an OTLP/HTTP receiver like `http://localhost:4318` when dropped
+    /// Only plain http is supported, the port defaults to 4318
+    pub fn send_otlp(&mut self, endpoint: impl Into<String>) { self.add_sink(OtlpSink::new(endpoint.into())); }
+
     pub fn set_starting_time(&mut self)
     {
-        self.record_start = TimePoint::now()
+        self.record_start = TimePoint::now();
     }
 }
 
 impl Drop for RecordScope
 {
-    fn drop(&mut self) { self.write().unwrap_or_else(|err| println!("Failed dump - Reason: {err}")); }
+    fn drop(&mut self)
+    {
+        session::deactivate(&self.session);
+        self.session.close();
+        self.write().unwrap_or_else(|err| println!("Failed dump - Reason: {err}"));
+    }
 }
 
 impl RecordScope
 {
+    /// Every sink is written on its own, one that fails is left out from then on and the others are still finished
     pub(super) fn write(&mut self) -> std::io::Result<()>
     {
-        //TODO allow appending as different process instead of overwriting
-        let writer = &mut BufWriter::new(File::create(&self.path)?);
-        let data = self.fetch_data();
-        json::to_writer(writer, &data)?;
+        let mut sinks: Vec<Box<dyn TraceSink>> = self.file.take().map(|file| Box::new(file) as Box<dyn TraceSink>).into_iter().collect();
+        sinks.append(&mut self.sinks);
+        let info = RecordingInfo {
+            // Wall clock time of the starting time, the same for all sinks
+            start: SystemTime::now() - self.record_start.elapsed(),
+            metadata: self.session.chrome_extras(),
+        };
+        let meta_traces = self.session.meta_traces().clone();
+        let mut errors = Vec::new();
+        sinks.retain_mut(|sink| {
+            let begun = sink.begin(&info).and_then(|()| {
+                meta_traces.iter().try_for_each(|meta_trace| match meta_trace
+                {
+                    MetaTrace::ProcessName(header, name) => sink.process_name(header, name),
+                    MetaTrace::ThreadName(header, thread, name) => sink.thread_name(header, *thread, name),
+                })
+            });
+            begun.map_err(|err| errors.push(err)).is_ok()
+        });
+
+        // Events that can not be read back end the recording early, the sinks are finished anyway
+        if let Err(err) = self.send_events(&mut sinks, &mut errors)
+        {
+            errors.push(err);
+        }
+        for sink in &mut sinks
+        {
+            if let Err(err) = sink.finish()
+            {
+                errors.push(err);
+            }
+        }
+        match errors.len()
+        {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(std::io::Error::other(errors.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))),
+        }
+    }
+
+    /// Hands all events to the `sinks` in batches, the ones that fail are removed
+    fn send_events(&mut self, sinks: &mut Vec<Box<dyn TraceSink>>, errors: &mut Vec<std::io::Error>) -> std::io::Result<()>
+    {
+        let mut send = |batch: &[Event]| sinks.retain_mut(|sink| sink.events(batch).map_err(|err| errors.push(err)).is_ok());
+        if self.streaming
+        {
+            // Only one event per spilled run is held in memory besides the batch
+            self.session.spill_all()?;
+            let offset = self.spill_offset();
+            let mut batch = Vec::with_capacity(BATCH);
+            for event in self.session.take_runs()?
+            {
+                batch.extend(event?.shift(offset));
+                if batch.len() == BATCH
+                {
+                    send(&batch);
+                    batch.clear();
+                }
+            }
+            send(&batch);
+        }
+        else
+        {
+            for batch in self.fetch_events()?.chunks(BATCH)
+            {
+                send(batch);
+            }
+        }
         Ok(())
     }
 
@@ -60,31 +225,62 @@ impl RecordScope
     pub fn add_meta_data(&mut self, name: String, data: &impl serde::Serialize) -> Result<Option<json::Value>, serde_json::Error>
     {
         let json = json::value::to_value(data)?;
-        Ok(self.meta_data.insert(name, json))
+        Ok(self.session.add_metadata(name, json))
     }
 
     pub fn name_thread(&mut self, thread_id: ThreadId, header: Pid, name: String)
     {
-        self.meta_traces.push(MetaTrace::ThreadName(header, thread_id, name));
+        self.session.add_meta_trace(MetaTrace::ThreadName(header.into(), print_tread_id(thread_id), name));
     }
 
     pub fn final_header(&mut self, old_header: Pid, new_header: String)
     {
-        self.meta_traces.push(MetaTrace::ProcessName(old_header, new_header));
+        self.session.add_meta_trace(MetaTrace::ProcessName(old_header.into(), new_header));
     }
 }
 
 type Pid = &'static str;
-type Tid = ThreadId;
+/// Printed thread id, owned to describe loaded recordings as well
+type Tid = u64;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) enum MetaTrace
 {
-    ProcessName(Pid, String), //__metadata M
+    ProcessName(Text, String), //__metadata M
     //ProcessSortIndex(Pid, usize),        //__metadata M todo!
     //ProcessLabels(Pid, String),        //__metadata M todo!
-    ThreadName(Pid, Tid, String), /* __metadata M
+    ThreadName(Text, Tid, String), /* __metadata M
                                    *ThreadSortIndex(Pid, Tid, usize),        //__metadata M todo!
                                    *ProcessUptimeSeconds(Pid, u128), //__metadata M Not in the doc
                                    *ActiveProcesses(Vec<Pid>, u128), //__metadata I s:g Not in the doc */
-}
\ No newline at end of file
+}
+impl MetaTrace
+{
+    /// Name given to `header` with [`RecordScope::final_header`], the header itself otherwise
+    pub(crate) fn process_name<'a>(meta_traces: &'a [Self], header: &'a str) -> &'a str
+    {
+        meta_traces
+            .iter()
+            .rev()
+            .find_map(|meta| match meta
+            {
+                MetaTrace::ProcessName(old, new) if old == header => Some(new.as_str()),
+                _ => None,
+            })
+            .unwrap_or(header)
+    }
+
+    /// Name given to the thread with [`RecordScope::name_thread`]
+    pub(crate) fn thread_name(meta_traces: &[Self], header: &str, thread: u64) -> String
+    {
+        meta_traces
+            .iter()
+            .rev()
+            .find_map(|meta| match meta
+            {
+                MetaTrace::ThreadName(pid, tid, name) if pid == header && *tid == thread => Some(name.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| format!("Thread {thread}"))
+    }
+}
@@ -0,0 +1,49 @@
+use std::{io, io::Write, path::Path, time::SystemTime};
+
+use serde_json::{Map, Value as JsonValue};
+
+use crate::{
+    Compression, Format, binary, callgrind, firefox, flamegraph, folded, model::Event, otlp, perfetto, pprof, record_scope::MetaTrace, speedscope
+};
+
+/// Everything the writers need, gathered from a finished recording or read back from a file
+pub(crate) struct Recording
+{
+    /// In order, relative to the starting time
+    pub events: Vec<Event>,
+    pub meta_traces: Vec<MetaTrace>,
+    /// Fields of the chrome trace next to the events, like the metadata
+    pub extras: Map<String, JsonValue>,
+    /// Wall clock time of the starting time
+    pub start: SystemTime,
+}
+
+impl Recording
+{
+    pub(crate) fn write(&self, path: &Path, format: Format, compression: Compression) -> io::Result<()>
+    {
+        //TODO allow appending as different process instead of overwriting
+        let mut file = compression.create(path)?;
+        let writer = &mut file;
+        let (events, meta_traces, start) = (&self.events, &self.meta_traces, self.start);
+        match format
+        {
+            Format::Json => serde_json::to_writer(writer, &self.chrome_data())?,
+            Format::Perfetto => writer.write_all(&perfetto::encode(events, meta_traces))?,
+            Format::Firefox => serde_json::to_writer(writer, &firefox::profile(events, meta_traces, start))?,
+            Format::Speedscope => serde_json::to_writer(writer, &speedscope::file(events, meta_traces))?,
+            Format::Folded => writer.write_all(folded::file(events).as_bytes())?,
+            Format::Flamegraph =>
+            {
+                let title = compression.strip(path);
+                let title = title.file_stem().and_then(|stem| stem.to_str()).unwrap_or("Flamegraph");
+                writer.write_all(flamegraph::svg(events, title).as_bytes())?;
+            },
+            Format::Pprof => writer.write_all(&pprof::encode(events, meta_traces, start))?,
+            Format::Callgrind => writer.write_all(callgrind::file(events).as_bytes())?,
+            Format::Binary => binary::write(self, writer)?,
+            Format::Otlp => writer.write_all(otlp::lines(&otlp::export(events, meta_traces, start)).as_bytes())?,
+        }
+        file.finish()
+    }
+}
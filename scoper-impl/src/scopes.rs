@@ -1,18 +1,29 @@
-use std::cell::RefCell;
+use std::{cell::RefCell, sync::Arc};
 
-use scoper_base::Info;
+use scoper_base::{Args, Info, Value};
 
-use crate::{record_custom_scope, TimePoint};
+use crate::{TimePoint, global::record_scope_with_args, session, strings::NameId};
 
 thread_local! {
-    static OPEN_SCOPES: RefCell<Vec<TimePoint>> = RefCell::default();
+    static OPEN_SCOPES: RefCell<Vec<OpenScope>> = RefCell::default();
 }
 
+#[derive(Clone)]
 pub(crate) struct Start(pub TimePoint);
 
+struct OpenScope
+{
+    start: TimePoint,
+    args: Args,
+    /// The name is kept to intern it again if the scope is closed in another session
+    name: Option<(NameId, Arc<str>)>,
+}
+
 pub struct Scope
 {
     info: Info,
+    /// Index of its slot in the open scopes of the thread
+    slot: usize,
 }
 
 impl Scope
@@ -20,8 +31,29 @@ impl Scope
     #[must_use]
     pub fn start(info: Info) -> Self
     {
-        open_scope();
-        Self { info }
+        let slot = open_scope(None);
+        Self { info, slot }
+    }
+
+    /// Scope with a name built at runtime, the static name of `info` is replaced
+    /// The name is interned into the session active on this thread
+    #[must_use]
+    pub fn start_named(info: Info, name: impl AsRef<str>) -> Self
+    {
+        let slot = open_scope(session::with_active(|active| active.session.intern(name.as_ref())));
+        Self { info, slot }
+    }
+
+    /// Attaches an argument to the scope, shown when the scope is selected in the viewer
+    /// Goes to this scope even while inner scopes are open
+    pub fn arg(&self, key: &'static str, value: impl Into<Value>)
+    {
+        OPEN_SCOPES.with_borrow_mut(|slots| {
+            if let Some(open) = slots.get_mut(self.slot)
+            {
+                open.args.push((key, value.into()));
+            }
+        });
     }
 }
 
@@ -30,8 +62,31 @@ impl Drop for Scope
     fn drop(&mut self) { close_scope(self); }
 }
 
-pub(super) fn open_scope() { OPEN_SCOPES.with_borrow_mut(|slots| slots.push(TimePoint::now())) }
-fn pop_scope_opening_time() -> TimePoint
+/// Attaches an argument to the innermost open scope of the current thread
+/// Useful for scopes created by `record_scope!` or `#[record]`
+pub fn record_arg(key: &'static str, value: impl Into<Value>)
+{
+    OPEN_SCOPES.with_borrow_mut(|slots| {
+        if let Some(open) = slots.last_mut()
+        {
+            open.args.push((key, value.into()));
+        }
+    });
+}
+
+/// Index of the new slot
+fn open_scope(name: Option<(NameId, Arc<str>)>) -> usize
+{
+    OPEN_SCOPES.with_borrow_mut(|slots| {
+        slots.push(OpenScope {
+            start: TimePoint::now(),
+            args: Args::new(),
+            name,
+        });
+        slots.len() - 1
+    })
+}
+fn pop_open_scope() -> OpenScope
 {
     OPEN_SCOPES.with_borrow_mut(|slots| slots.pop().unwrap())
     /*
@@ -56,8 +111,8 @@ fn pop_scope_opening_time() -> TimePoint
     */
 }
 
-pub(super) fn close_scope(Scope { info }: &Scope)
+pub(super) fn close_scope(Scope { info, .. }: &Scope)
 {
-    let start = pop_scope_opening_time();
-    record_custom_scope(info, start, TimePoint::now()); //might require a check to ensure the ends are sorted
+    let OpenScope { start, args, name } = pop_open_scope();
+    record_scope_with_args(info, start, TimePoint::now(), args, name); //might require a check to ensure the ends are sorted
 }
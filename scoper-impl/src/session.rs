@@ -0,0 +1,642 @@
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter},
+    marker::PhantomData,
+    path::PathBuf,
+    sync::{
+        Arc, Condvar, Mutex, Weak,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use crossbeam_queue::SegQueue;
+use scoper_base::{InstantScopeSize, Value};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::{
+    TimePoint,
+    limits::{BufferLimits, DroppedEvents, EventKind, OverflowPolicy},
+    merge::{self, Merge, Run},
+    model::Event,
+    record_scope::MetaTrace,
+    scopes::Start,
+    strings::{self, NameId, StringTable, Strings},
+    types::{AsyncEvent, BaseInfo, FlowEvent, ObjectEvent, TaggedData, TaggedTrace, Trace},
+};
+
+thread_local! {
+    static ACTIVE_SESSIONS: RefCell<Vec<Active>> = RefCell::default();
+    /// Buffers of this thread in every session it entered, reused when entering again
+    static REGISTERED: RefCell<Vec<(Weak<Session>, Arc<ThreadBuffers>)>> = RefCell::default();
+}
+
+/// Collects the events of a single recording
+/// Every thread writes into its own buffers, they are only gathered when flushing
+pub(crate) struct Session
+{
+    /// Unique in the process, runtime names are tagged with it
+    pub(crate) id: u64,
+    threads: Mutex<Vec<Arc<ThreadBuffers>>>,
+    /// Events of all threads in the order they were pushed, used instead of the thread buffers under [`OverflowPolicy::DropOldest`]
+    ring: Buffer<TaggedTrace>,
+    strings: Mutex<StringTable>,
+    pub(crate) limits: BufferLimits,
+    /// Events in the buffers of all threads, only counted if the capacity is bounded
+    buffered: AtomicUsize,
+    pub(crate) epoch: TimePoint,
+    pub(crate) dropped: DroppedEvents,
+    /// Added with [`RecordScope::add_meta_data`](crate::RecordScope::add_meta_data)
+    metadata: Mutex<Map<String, JsonValue>>,
+    meta_traces: Mutex<Vec<MetaTrace>>,
+    /// Nanoseconds since the epoch when events are next checked against `max_age`
+    next_expiry: AtomicU64,
+    spill: Mutex<Spill>,
+    /// Signaled whenever buffers were emptied, paired with `spill`
+    room: Condvar,
+    closed: AtomicBool,
+}
+
+impl Session
+{
+    pub(crate) fn new(limits: BufferLimits, spill_path: PathBuf) -> Arc<Self>
+    {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        Arc::new(Self {
+            id,
+            threads: Mutex::default(),
+            ring: Buffer::default(),
+            strings: Mutex::new(StringTable::new(id)),
+            limits,
+            buffered: AtomicUsize::new(0),
+            epoch: TimePoint::now(),
+            dropped: DroppedEvents::default(),
+            metadata: Mutex::default(),
+            meta_traces: Mutex::default(),
+            next_expiry: AtomicU64::new(0),
+            spill: Mutex::new(Spill {
+                path: spill_path,
+                writer: None,
+                written: 0,
+                runs: Vec::new(),
+            }),
+            room: Condvar::new(),
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// Interned runtime name together with its id, repeated names of a thread do not lock the table
+    pub(crate) fn intern(&self, name: &str) -> (NameId, Arc<str>) { strings::intern(&self.strings, self.id, name) }
+
+    pub(crate) fn strings(&self) -> Strings { self.strings.lock().expect("Could not get access").strings() }
+
+    pub(crate) fn add_meta_trace(&self, trace: MetaTrace) { self.meta_traces.lock().expect("Could not get access").push(trace); }
+
+    pub(crate) fn meta_traces(&self) -> std::sync::MutexGuard<'_, Vec<MetaTrace>> { self.meta_traces.lock().expect("Could not get access") }
+
+    /// Returns the previous value with that name if any is present
+    pub(crate) fn add_metadata(&self, name: String, value: JsonValue) -> Option<JsonValue>
+    {
+        self.metadata.lock().expect("Could not get access").insert(name, value)
+    }
+
+    /// Fields of the chrome trace next to the events
+    pub(crate) fn chrome_extras(&self) -> Map<String, JsonValue>
+    {
+        let mut data = Map::new();
+        if let Some(dropped) = self.dropped.json_format()
+        {
+            data.insert("droppedEvents".to_string(), dropped);
+        }
+        data.extend(self.metadata.lock().expect("Could not get access").clone());
+        data
+    }
+
+    fn register(&self) -> Arc<ThreadBuffers>
+    {
+        let buffers = Arc::new(ThreadBuffers::default());
+        self.threads.lock().expect("Could not get access").push(buffers.clone());
+        buffers
+    }
+
+    /// Takes up room for one more event, `false` if the capacity is reached
+    fn reserve(&self) -> bool
+    {
+        let capacity = self.limits.capacity;
+        capacity == usize::MAX || self.buffered.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| (count < capacity).then_some(count + 1)).is_ok()
+    }
+
+    /// Gives back the room of `count` events that left the buffers
+    fn release(&self, count: usize)
+    {
+        if self.limits.capacity != usize::MAX
+        {
+            self.buffered.fetch_sub(count, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn flush(&self) -> impl Iterator<Item = TaggedTrace> + use<>
+    {
+        let mut threads = self.threads.lock().expect("Could not get access");
+        let traces: Vec<_> = threads.iter().flat_map(|buffers| buffers.flush()).chain(self.ring.flush()).collect();
+        self.release(traces.len());
+        // Buffers of exited threads are only referenced by the session
+        threads.retain(|buffers| Arc::strong_count(buffers) > 1);
+        traces.into_iter()
+    }
+
+    /// Removes the events that ended before the `max_age` window up to `now`
+    pub(crate) fn expire(&self, now: TimePoint)
+    {
+        let Some(zero) = self.limits.max_age.and_then(|max_age| now.checked_sub(max_age))
+        else
+        {
+            return;
+        };
+        let threads = self.threads.lock().expect("Could not get access");
+        let keep = |info: &BaseInfo| info.time_point >= zero;
+        for buffers in threads.iter()
+        {
+            self.release(buffers.retain(&keep, &self.dropped, None));
+        }
+        self.release(self.ring.retain(&keep, &self.dropped, None));
+    }
+
+    /// Like [`Session::expire`] but at most twice per `max_age`, the buffers only grow to about one and a half windows
+    fn expire_due(&self, now: TimePoint, max_age: Duration)
+    {
+        let nanos = |duration: Duration| u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        let elapsed = nanos(now.saturating_duration_since(self.epoch));
+        let due = self.next_expiry.load(Ordering::Relaxed);
+        // Only one of the threads pushing at that moment does the work
+        if elapsed >= due && self.next_expiry.compare_exchange(due, elapsed + nanos(max_age / 2), Ordering::Relaxed, Ordering::Relaxed).is_ok()
+        {
+            self.expire(now);
+        }
+    }
+
+    /// Copies of the events of all threads still inside the `max_age` window up to `now`, older ones are removed
+    pub(crate) fn snapshot(&self, now: TimePoint) -> Vec<TaggedTrace>
+    {
+        let zero = self.limits.max_age.and_then(|max_age| now.checked_sub(max_age));
+        let mut traces = Vec::new();
+        let threads = self.threads.lock().expect("Could not get access");
+        let keep = |info: &BaseInfo| zero.is_none_or(|zero| info.time_point >= zero);
+        for buffers in threads.iter()
+        {
+            self.release(buffers.retain(&keep, &self.dropped, Some(&mut traces)));
+        }
+        self.release(self.ring.retain(&keep, &self.dropped, Some(&mut traces)));
+        traces
+    }
+
+    /// `false` once the recording was dropped, possibly on another thread than the one that started it
+    fn is_open(&self) -> bool { !self.closed.load(Ordering::Acquire) }
+
+    /// Releases threads blocked on a full buffer, their further events are dropped
+    pub(crate) fn close(&self)
+    {
+        self.closed.store(true, Ordering::Release);
+        self.room.notify_all();
+    }
+
+    /// Moves the events of all threads into the spill file
+    pub(crate) fn spill_all(&self) -> io::Result<()>
+    {
+        let threads = self.threads.lock().expect("Could not get access");
+        self.spill(threads.iter().flat_map(|buffers| buffers.flush()).chain(self.ring.flush()).collect())
+    }
+
+    /// Moves `traces` into the spill file as one ordered run, their times are relative to the epoch
+    /// If that fails they are counted as dropped
+    fn spill(&self, traces: Vec<TaggedTrace>) -> io::Result<()>
+    {
+        self.release(traces.len());
+        let mut events = self.events(traces.into_iter(), self.epoch);
+        Event::sort(&mut events);
+        let written = self.write_run(&events);
+        if written.is_err()
+        {
+            // They already left the buffers
+            for event in &events
+            {
+                self.dropped.add(EventKind::of(&event.data));
+            }
+            self.dropped.add_failed_spill();
+        }
+        self.room.notify_all();
+        written
+    }
+
+    fn write_run(&self, events: &[Event]) -> io::Result<()>
+    {
+        let mut spill = self.spill.lock().expect("Could not get access");
+        let mut run = Run { start: spill.written, len: 0 };
+        let writer = spill.writer()?;
+        let mut written = 0;
+        for event in events
+        {
+            written += merge::write_event(writer, event)?;
+            run.len += 1;
+        }
+        spill.written += written;
+        if run.len > 0
+        {
+            spill.runs.push(run);
+        }
+        Ok(())
+    }
+
+    /// Reads back and removes the spill file, the times are relative to the epoch
+    pub(crate) fn take_spilled(&self) -> io::Result<Vec<Event>>
+    {
+        let mut spill = self.spill.lock().expect("Could not get access");
+        let Some(writer) = spill.writer.take()
+        else
+        {
+            return Ok(Vec::new());
+        };
+        writer.into_inner().map_err(io::IntoInnerError::into_error)?;
+        spill.written = 0;
+        spill.runs.clear();
+        let events = BufReader::new(File::open(&spill.path)?)
+            .lines()
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect::<io::Result<_>>()?;
+        std::fs::remove_file(&spill.path)?;
+        Ok(events)
+    }
+
+    /// The spilled runs merged in order, the spill file is removed once it was read
+    pub(crate) fn take_runs(&self) -> io::Result<Merge>
+    {
+        let mut spill = self.spill.lock().expect("Could not get access");
+        let Some(writer) = spill.writer.take()
+        else
+        {
+            return Ok(Merge::empty());
+        };
+        writer.into_inner().map_err(io::IntoInnerError::into_error)?;
+        spill.written = 0;
+        Merge::new(spill.path.clone(), std::mem::take(&mut spill.runs))
+    }
+
+    /// Spills until room for one more event is reserved, other threads can fill the buffers again in between
+    /// `false` if a spill failed, that is counted with the dropped events
+    fn spill_for_room(&self) -> bool
+    {
+        loop
+        {
+            if self.spill_all().is_err()
+            {
+                return false;
+            }
+            if self.reserve()
+            {
+                return true;
+            }
+        }
+    }
+
+    /// Makes room for one more event by dropping the oldest one of all threads, `false` if the capacity is zero
+    fn drop_oldest(&self) -> bool
+    {
+        loop
+        {
+            // Takes over the room of the dropped event
+            if let Some(oldest) = self.ring.pop()
+            {
+                self.dropped.add(oldest.1.kind());
+                return true;
+            }
+            if self.reserve()
+            {
+                return true;
+            }
+            if self.limits.capacity == 0
+            {
+                return false;
+            }
+            // The room is reserved by threads that did not push their events yet
+            std::thread::yield_now();
+        }
+    }
+
+    /// Waits until room for one more event is reserved, `false` if the session was closed before
+    fn wait_for_room(&self) -> bool
+    {
+        let mut guard = self.spill.lock().expect("Could not get access");
+        loop
+        {
+            if self.reserve()
+            {
+                return true;
+            }
+            if self.closed.load(Ordering::Acquire)
+            {
+                return false;
+            }
+            // Buffers are also emptied by flushes that do not signal
+            guard = self.room.wait_timeout(guard, Duration::from_millis(10)).expect("Could not get access").0;
+        }
+    }
+}
+
+/// Events moved out of memory, one json encoded [`Event`] per line
+/// Every spill appends an ordered run
+struct Spill
+{
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+    /// Bytes written so far
+    written: u64,
+    runs: Vec<Run>,
+}
+
+impl Spill
+{
+    fn writer(&mut self) -> io::Result<&mut BufWriter<File>>
+    {
+        if self.writer.is_none()
+        {
+            self.writer = Some(BufWriter::new(File::create(&self.path)?));
+        }
+        Ok(self.writer.as_mut().expect("Created above"))
+    }
+}
+
+/// Events recorded by one thread
+/// [`AsyncSpan`](crate::AsyncSpan) and [`Traced`](crate::Traced) keep pushing into them from the threads they are moved to
+#[derive(Default)]
+pub(crate) struct ThreadBuffers
+{
+    scopes: Buffer<Trace<Start>>,
+    counters: Buffer<Trace<Value>>,
+    instances: Buffer<Trace<InstantScopeSize>>,
+    asyncs: Buffer<Trace<AsyncEvent>>,
+    flows: Buffer<Trace<FlowEvent>>,
+    objects: Buffer<Trace<ObjectEvent>>,
+}
+
+impl ThreadBuffers
+{
+    fn flush(&self) -> impl Iterator<Item = TaggedTrace>
+    {
+        self.scopes
+            .flush()
+            .map(Trace::tag)
+            .chain(self.counters.flush().map(Trace::tag))
+            .chain(self.instances.flush().map(Trace::tag))
+            .chain(self.asyncs.flush().map(Trace::tag))
+            .chain(self.flows.flush().map(Trace::tag))
+            .chain(self.objects.flush().map(Trace::tag))
+    }
+
+    /// Removes the events `keep` rejects, copies of the others are added to `copies`
+    /// Returns the number of removed events
+    fn retain(&self, keep: &impl Fn(&BaseInfo) -> bool, dropped: &DroppedEvents, mut copies: Option<&mut Vec<TaggedTrace>>) -> usize
+    {
+        self.scopes.retain(keep, dropped, copies.as_deref_mut())
+            + self.counters.retain(keep, dropped, copies.as_deref_mut())
+            + self.instances.retain(keep, dropped, copies.as_deref_mut())
+            + self.asyncs.retain(keep, dropped, copies.as_deref_mut())
+            + self.flows.retain(keep, dropped, copies.as_deref_mut())
+            + self.objects.retain(keep, dropped, copies)
+    }
+}
+
+/// Event data that is counted as one [`EventKind`] when dropped
+pub(crate) trait Kind
+{
+    fn kind(&self) -> EventKind;
+}
+
+impl<P: Payload> Kind for P
+{
+    fn kind(&self) -> EventKind { P::KIND }
+}
+
+impl Kind for TaggedData
+{
+    fn kind(&self) -> EventKind
+    {
+        match self
+        {
+            Self::Scope(_) => EventKind::Scope,
+            Self::Counter(_) => EventKind::Counter,
+            Self::Instant(_) => EventKind::Instant,
+            Self::Async(_) => EventKind::Async,
+            Self::Flow(_) => EventKind::Flow,
+            Self::Object(_) => EventKind::Object,
+        }
+    }
+}
+
+/// Event payloads together with the buffer they are kept in
+pub(crate) trait Payload: Into<TaggedData> + Clone
+{
+    const KIND: EventKind;
+
+    fn buffer(buffers: &ThreadBuffers) -> &Buffer<Trace<Self>>;
+}
+
+macro_rules! payload {
+    ($($payload:ty => $kind:ident, $field:ident;)*) => {$(
+        impl Payload for $payload
+        {
+            const KIND: EventKind = EventKind::$kind;
+
+            fn buffer(buffers: &ThreadBuffers) -> &Buffer<Trace<Self>> { &buffers.$field }
+        }
+    )*};
+}
+
+payload! {
+    Start => Scope, scopes;
+    Value => Counter, counters;
+    InstantScopeSize => Instant, instances;
+    AsyncEvent => Async, asyncs;
+    FlowEvent => Flow, flows;
+    ObjectEvent => Object, objects;
+}
+
+/// A session together with the buffers of the thread that activated it
+#[derive(Clone)]
+pub(crate) struct Active
+{
+    pub(crate) session: Arc<Session>,
+    pub(crate) buffers: Arc<ThreadBuffers>,
+}
+
+impl Active
+{
+    /// Buffers the event, a full session is handled by its [`OverflowPolicy`]
+    pub(crate) fn push<P: Payload>(&self, trace: Trace<P>)
+    {
+        let session = &self.session;
+        let buffer = P::buffer(&self.buffers);
+        let BufferLimits { policy, max_age, .. } = session.limits;
+        if let Some(max_age) = max_age
+        {
+            session.expire_due(trace.0.time_point, max_age);
+        }
+        if !session.reserve()
+        {
+            let has_room = match policy
+            {
+                OverflowPolicy::DropNewest => false,
+                OverflowPolicy::DropOldest => session.drop_oldest(),
+                OverflowPolicy::FlushToDisk => session.spill_for_room(),
+                OverflowPolicy::Block => session.wait_for_room(),
+            };
+            if !has_room
+            {
+                session.dropped.add(P::KIND);
+                return;
+            }
+        }
+        if policy == OverflowPolicy::DropOldest
+        {
+            session.ring.push(trace.tag());
+        }
+        else
+        {
+            buffer.push(trace);
+        }
+    }
+}
+
+/// Runs `f` with the innermost session active on this thread
+/// Events recorded without an active session are discarded
+/// Sessions of recordings dropped on another thread are skipped
+pub(crate) fn with_active<R>(f: impl FnOnce(&Active) -> R) -> Option<R>
+{
+    ACTIVE_SESSIONS.with_borrow(|sessions| sessions.iter().rev().find(|active| active.session.is_open()).map(f))
+}
+
+/// The innermost session active on this thread
+pub(crate) fn active() -> Option<Active> { with_active(Active::clone) }
+
+pub(crate) fn activate(session: &Arc<Session>)
+{
+    let buffers = REGISTERED.with_borrow_mut(|registered| {
+        registered.retain(|(session, _)| session.strong_count() > 0);
+        if let Some((_, buffers)) = registered.iter().find(|(registered, _)| registered.as_ptr() == Arc::as_ptr(session))
+        {
+            return buffers.clone();
+        }
+        let buffers = session.register();
+        registered.push((Arc::downgrade(session), buffers.clone()));
+        buffers
+    });
+    ACTIVE_SESSIONS.with_borrow_mut(|sessions| {
+        // Left behind by recordings dropped on another thread
+        sessions.retain(|active| active.session.is_open());
+        sessions.push(Active {
+            session: session.clone(),
+            buffers,
+        });
+    });
+}
+
+pub(crate) fn deactivate(session: &Arc<Session>)
+{
+    ACTIVE_SESSIONS.with_borrow_mut(|sessions| {
+        if let Some(index) = sessions.iter().rposition(|active| Arc::ptr_eq(&active.session, session))
+        {
+            sessions.remove(index);
+        }
+    });
+}
+
+/// Handle to the session of a [`RecordScope`](crate::RecordScope)
+/// Can be sent to other threads to record their events into the same file
+#[derive(Clone)]
+pub struct SessionHandle(pub(crate) Arc<Session>);
+
+impl SessionHandle
+{
+    /// Session the current thread records into, if any
+    #[must_use]
+    pub fn current() -> Option<Self> { active().map(|active| Self(active.session)) }
+
+    /// Records all events of the current thread into this session until the
+    /// guard is dropped
+    #[must_use]
+    pub fn enter(&self) -> SessionGuard
+    {
+        activate(&self.0);
+        SessionGuard {
+            session: self.0.clone(),
+            _not_send: PhantomData,
+        }
+    }
+}
+
+/// Keeps a session active on the current thread
+pub struct SessionGuard
+{
+    session: Arc<Session>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for SessionGuard
+{
+    fn drop(&mut self) { deactivate(&self.session); }
+}
+
+/// Lock-free chunked queue, pushing only blocks under [`OverflowPolicy::Block`]
+pub(crate) struct Buffer<Data>
+{
+    buffer: SegQueue<Data>,
+}
+
+impl<Data> Default for Buffer<Data>
+{
+    fn default() -> Self { Self { buffer: SegQueue::new() } }
+}
+
+impl<Data> Buffer<Data>
+{
+    fn push(&self, value: Data) { self.buffer.push(value); }
+
+    fn pop(&self) -> Option<Data> { self.buffer.pop() }
+
+    fn len(&self) -> usize { self.buffer.len() }
+
+    fn flush(&self) -> impl Iterator<Item = Data> { std::iter::from_fn(|| self.buffer.pop()) }
+}
+
+impl<D: Kind + Into<TaggedData> + Clone> Buffer<Trace<D>>
+{
+    /// Removes the events `keep` rejects, copies of the others are added to `copies`
+    /// Rotating through the whole queue keeps the order, events pushed meanwhile by other threads can end up in between
+    fn retain(&self, keep: &impl Fn(&BaseInfo) -> bool, dropped: &DroppedEvents, mut copies: Option<&mut Vec<TaggedTrace>>) -> usize
+    {
+        let mut removed = 0;
+        for _ in 0..self.len()
+        {
+            let Some(trace) = self.pop()
+            else
+            {
+                break;
+            };
+            if keep(&trace.0)
+            {
+                if let Some(copies) = copies.as_deref_mut()
+                {
+                    copies.push(trace.clone().tag());
+                }
+                self.push(trace);
+            }
+            else
+            {
+                dropped.add(trace.1.kind());
+                removed += 1;
+            }
+        }
+        removed
+    }
+}
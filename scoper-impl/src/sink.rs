@@ -0,0 +1,231 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{Map, Value as JsonValue};
+
+use crate::{Compression, Format, binary::BinarySink, compression::Encoder, json::ChromeJsonSink, model::Event, otlp, record_scope::MetaTrace, recording::Recording};
+
+/// Describes a finished recording before its events are handed to a [`TraceSink`]
+#[derive(Debug, Clone)]
+pub struct RecordingInfo
+{
+    /// Wall clock time of the starting time, all event times are relative to it
+    pub start: SystemTime,
+    /// Metadata added with [`RecordScope::add_meta_data`](crate::RecordScope::add_meta_data) and the dropped events
+    pub metadata: Map<String, JsonValue>,
+}
+
+/// Destination of a recording, like a file, a socket or an in-memory buffer
+/// [`TraceSink::begin`] is called first, then the names, then the events in batches and [`TraceSink::finish`] last
+pub trait TraceSink: Send
+{
+    /// Starts the recording
+    ///
+    /// # Errors
+    /// Returns an Error if the sink can not be written
+    fn begin(&mut self, _info: &RecordingInfo) -> io::Result<()> { Ok(()) }
+
+    /// Name given to `header` with [`RecordScope::final_header`](crate::RecordScope::final_header)
+    ///
+    /// # Errors
+    /// Returns an Error if the sink can not be written
+    fn process_name(&mut self, _header: &str, _name: &str) -> io::Result<()> { Ok(()) }
+
+    /// Name given to a thread with [`RecordScope::name_thread`](crate::RecordScope::name_thread)
+    ///
+    /// # Errors
+    /// Returns an Error if the sink can not be written
+    fn thread_name(&mut self, _header: &str, _thread: u64, _name: &str) -> io::Result<()> { Ok(()) }
+
+    /// Next batch of events, all batches together are ordered by start, enclosing scopes first
+    ///
+    /// # Errors
+    /// Returns an Error if the sink can not be written
+    fn events(&mut self, events: &[Event]) -> io::Result<()>;
+
+    /// Ends the recording, nothing follows
+    ///
+    /// # Errors
+    /// Returns an Error if the sink can not be written
+    fn finish(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+impl TraceSink for Box<dyn TraceSink>
+{
+    fn begin(&mut self, info: &RecordingInfo) -> io::Result<()> { (**self).begin(info) }
+
+    fn process_name(&mut self, header: &str, name: &str) -> io::Result<()> { (**self).process_name(header, name) }
+
+    fn thread_name(&mut self, header: &str, thread: u64, name: &str) -> io::Result<()> { (**self).thread_name(header, thread, name) }
+
+    fn events(&mut self, events: &[Event]) -> io::Result<()> { (**self).events(events) }
+
+    fn finish(&mut self) -> io::Result<()> { (**self).finish() }
+}
+
+/// Writes the recording to a file
+/// Json and binary traces are written while the events arrive, the other formats gather them until finished
+pub struct FileSink
+{
+    pub(crate) path: PathBuf,
+    pub(crate) format: Format,
+    pub(crate) compression: Compression,
+    state: FileState,
+}
+
+enum FileState
+{
+    Idle,
+    Json(ChromeJsonSink<Encoder>),
+    Binary(BinarySink<Encoder>),
+    Collect(Recording),
+}
+
+impl FileSink
+{
+    /// Format and compression are picked by the extension of `path`, json gets a `.json` extension
+    pub fn new(path: impl AsRef<Path>) -> Self
+    {
+        let format = Format::from_path(path.as_ref());
+        let path = format.output_path(path.as_ref());
+        let compression = Compression::from_path(&path);
+        Self::with_format(path, format, compression)
+    }
+
+    /// Writes `format` with `compression` to `path` as it is
+    pub fn with_format(path: impl Into<PathBuf>, format: Format, compression: Compression) -> Self
+    {
+        Self {
+            path: path.into(),
+            format,
+            compression,
+            state: FileState::Idle,
+        }
+    }
+
+    fn sink(&mut self) -> io::Result<&mut dyn TraceSink>
+    {
+        match &mut self.state
+        {
+            FileState::Idle => Err(io::Error::other(format!("Recording of {} was not begun", self.path.display()))),
+            FileState::Json(sink) => Ok(sink),
+            FileState::Binary(sink) => Ok(sink),
+            FileState::Collect(recording) => Ok(recording),
+        }
+    }
+}
+
+impl TraceSink for FileSink
+{
+    fn begin(&mut self, info: &RecordingInfo) -> io::Result<()>
+    {
+        //TODO allow appending as different process instead of overwriting
+        self.state = match self.format
+        {
+            Format::Json => FileState::Json(ChromeJsonSink::new(self.compression.create(&self.path)?)),
+            Format::Binary => FileState::Binary(BinarySink::new(self.compression.create(&self.path)?)),
+            _ => FileState::Collect(Recording::empty()),
+        };
+        self.sink()?.begin(info)
+    }
+
+    fn process_name(&mut self, header: &str, name: &str) -> io::Result<()> { self.sink()?.process_name(header, name) }
+
+    fn thread_name(&mut self, header: &str, thread: u64, name: &str) -> io::Result<()> { self.sink()?.thread_name(header, thread, name) }
+
+    fn events(&mut self, events: &[Event]) -> io::Result<()> { self.sink()?.events(events) }
+
+    fn finish(&mut self) -> io::Result<()>
+    {
+        match std::mem::replace(&mut self.state, FileState::Idle)
+        {
+            FileState::Idle => Ok(()),
+            FileState::Json(mut sink) =>
+            {
+                sink.finish()?;
+                sink.into_inner().finish()
+            },
+            FileState::Binary(mut sink) =>
+            {
+                sink.finish()?;
+                sink.into_inner().finish()
+            },
+            FileState::Collect(recording) => recording.write(&self.path, self.format, self.compression),
+        }
+    }
+}
+
+/// Sends the recording to an OTLP/HTTP receiver once finished
+pub(crate) struct OtlpSink
+{
+    endpoint: String,
+    recording: Recording,
+}
+
+impl OtlpSink
+{
+    pub(crate) fn new(endpoint: String) -> Self { Self { endpoint, recording: Recording::empty() } }
+}
+
+impl TraceSink for OtlpSink
+{
+    fn begin(&mut self, info: &RecordingInfo) -> io::Result<()> { self.recording.begin(info) }
+
+    fn process_name(&mut self, header: &str, name: &str) -> io::Result<()> { self.recording.process_name(header, name) }
+
+    fn thread_name(&mut self, header: &str, thread: u64, name: &str) -> io::Result<()> { self.recording.thread_name(header, thread, name) }
+
+    fn events(&mut self, events: &[Event]) -> io::Result<()> { self.recording.events(events) }
+
+    fn finish(&mut self) -> io::Result<()>
+    {
+        let recording = std::mem::replace(&mut self.recording, Recording::empty());
+        otlp::send(&self.endpoint, &otlp::export(&recording.events, &recording.meta_traces, recording.start))
+    }
+}
+
+impl Recording
+{
+    /// Nothing recorded yet, filled by its [`TraceSink`] implementation
+    pub(crate) fn empty() -> Self
+    {
+        Self {
+            events: Vec::new(),
+            meta_traces: Vec::new(),
+            extras: Map::new(),
+            start: UNIX_EPOCH,
+        }
+    }
+}
+
+/// Gathers everything in memory
+impl TraceSink for Recording
+{
+    fn begin(&mut self, info: &RecordingInfo) -> io::Result<()>
+    {
+        self.start = info.start;
+        self.extras.clone_from(&info.metadata);
+        Ok(())
+    }
+
+    fn process_name(&mut self, header: &str, name: &str) -> io::Result<()>
+    {
+        self.meta_traces.push(MetaTrace::ProcessName(header.to_string().into(), name.to_string()));
+        Ok(())
+    }
+
+    fn thread_name(&mut self, header: &str, thread: u64, name: &str) -> io::Result<()>
+    {
+        self.meta_traces.push(MetaTrace::ThreadName(header.to_string().into(), thread, name.to_string()));
+        Ok(())
+    }
+
+    fn events(&mut self, events: &[Event]) -> io::Result<()>
+    {
+        self.events.extend_from_slice(events);
+        Ok(())
+    }
+}
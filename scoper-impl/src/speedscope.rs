@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use serde_json::{Value as JsonValue, json};
+
+use crate::{
+    model::{Event, Text},
+    nesting::{self, Step},
+    record_scope::MetaTrace,
+};
+
+/// Speedscope file of the scopes in `events`, one evented profile per thread and header
+/// The profiles are named "header: thread", frames are shared by name and category
+pub(crate) fn file(events: &[Event], meta_traces: &[MetaTrace]) -> JsonValue
+{
+    let mut frames: HashMap<(&Text, &Text), usize> = HashMap::new();
+    let mut shared = Vec::new();
+    let mut profiles = Vec::new();
+    for thread in nesting::threads(events)
+    {
+        let frame_events: Vec<_> = thread
+            .steps()
+            .into_iter()
+            .map(|step| match step
+            {
+                Step::Open(index) => json!({"type": "O", "frame": frame(&mut frames, &mut shared, thread.spans[index].event), "at": thread.spans[index].start()}),
+                Step::Close(index) => json!({"type": "C", "frame": frame(&mut frames, &mut shared, thread.spans[index].event), "at": thread.spans[index].end}),
+            })
+            .collect();
+        let start = thread.spans.first().map_or(0, nesting::Span::start);
+        let end = thread.spans.iter().map(|span| span.end).max().unwrap_or(start);
+        profiles.push(json!({
+            "type": "evented",
+            "name": format!("{}: {}", MetaTrace::process_name(meta_traces, thread.header), MetaTrace::thread_name(meta_traces, thread.header, thread.thread)),
+            "unit": "nanoseconds",
+            "startValue": start,
+            "endValue": end,
+            "events": frame_events,
+        }));
+    }
+    json!({
+        "$schema": "https://www.speedscope.app/file-format-schema.json",
+        "exporter": "scoper",
+        "activeProfileIndex": 0,
+        "shared": {"frames": shared},
+        "profiles": profiles,
+    })
+}
+
+/// Index of the shared frame of `event`
+fn frame<'a>(frames: &mut HashMap<(&'a Text, &'a Text), usize>, shared: &mut Vec<JsonValue>, event: &'a Event) -> usize
+{
+    *frames.entry((&event.name, &event.category)).or_insert_with(|| {
+        shared.push(if event.category.is_empty()
+        {
+            json!({"name": event.name})
+        }
+        else
+        {
+            json!({"name": event.name, "file": event.category})
+        });
+        shared.len() - 1
+    })
+}
@@ -0,0 +1,199 @@
+use std::{collections::BTreeMap, str::FromStr, time::Duration};
+
+use crate::{Trace, model::Event, nesting};
+
+/// Timing of all scopes with the same name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeStats
+{
+    pub name: String,
+    pub count: usize,
+    /// Summed duration, nested calls of the same name are counted again
+    pub total: Duration,
+    /// Summed time not spent in any of the children
+    pub self_time: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub max: Duration,
+}
+
+impl Trace
+{
+    /// Statistics of every scope name, the largest total first
+    #[must_use]
+    pub fn scope_stats(&self) -> Vec<ScopeStats> { scope_stats(&self.0.events) }
+}
+
+pub(crate) fn scope_stats(events: &[Event]) -> Vec<ScopeStats>
+{
+    // Durations and summed self time of every name
+    let mut scopes: BTreeMap<&str, (Vec<u64>, u64)> = BTreeMap::new();
+    let threads = nesting::threads(events);
+    for span in threads.iter().flat_map(|thread| &thread.spans)
+    {
+        let (durations, self_time) = scopes.entry(&span.event.name).or_default();
+        durations.push(span.duration());
+        *self_time += span.self_time();
+    }
+    let mut stats: Vec<_> = scopes
+        .into_iter()
+        .map(|(name, (mut durations, self_time))| {
+            durations.sort_unstable();
+            let total: u64 = durations.iter().sum();
+            ScopeStats {
+                name: name.to_string(),
+                count: durations.len(),
+                total: Duration::from_nanos(total),
+                self_time: Duration::from_nanos(self_time),
+                mean: Duration::from_nanos(total / durations.len() as u64),
+                p50: percentile(&durations, 50),
+                p95: percentile(&durations, 95),
+                max: Duration::from_nanos(durations.last().copied().unwrap_or_default()),
+            }
+        })
+        .collect();
+    stats.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.name.cmp(&b.name)));
+    stats
+}
+
+/// Nearest rank percentile of the sorted `durations`
+fn percentile(durations: &[u64], percent: usize) -> Duration
+{
+    let rank = (durations.len() * percent).div_ceil(100).max(1);
+    Duration::from_nanos(durations[rank - 1])
+}
+
+/// Statistic two traces are compared by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Metric
+{
+    Count,
+    #[default]
+    Total,
+    SelfTime,
+    Mean,
+    P50,
+    P95,
+    Max,
+}
+
+impl Metric
+{
+    pub const ALL: [Self; 7] = [Self::Count, Self::Total, Self::SelfTime, Self::Mean, Self::P50, Self::P95, Self::Max];
+
+    /// Number of calls for [`Metric::Count`], nanoseconds otherwise
+    #[must_use]
+    pub fn of(self, stats: &ScopeStats) -> u64
+    {
+        let duration = match self
+        {
+            Self::Count => return stats.count as u64,
+            Self::Total => stats.total,
+            Self::SelfTime => stats.self_time,
+            Self::Mean => stats.mean,
+            Self::P50 => stats.p50,
+            Self::P95 => stats.p95,
+            Self::Max => stats.max,
+        };
+        duration.as_nanos().try_into().unwrap_or(u64::MAX)
+    }
+
+    #[must_use]
+    pub const fn name(self) -> &'static str
+    {
+        match self
+        {
+            Self::Count => "count",
+            Self::Total => "total",
+            Self::SelfTime => "self",
+            Self::Mean => "mean",
+            Self::P50 => "p50",
+            Self::P95 => "p95",
+            Self::Max => "max",
+        }
+    }
+}
+
+impl FromStr for Metric
+{
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>
+    {
+        Self::ALL.into_iter().find(|metric| metric.name() == name).ok_or_else(|| {
+            let names: Vec<_> = Self::ALL.iter().map(|metric| metric.name()).collect();
+            format!("Unknown metric {name}, use one of {}", names.join(", "))
+        })
+    }
+}
+
+/// Change of the scopes with the same name between two traces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeDiff
+{
+    pub name: String,
+    /// `None` if the scope is new
+    pub before: Option<ScopeStats>,
+    /// `None` if the scope is gone
+    pub after: Option<ScopeStats>,
+}
+
+impl ScopeDiff
+{
+    /// `metric` before and after, zero if the scope is missing on that side
+    #[must_use]
+    pub fn values(&self, metric: Metric) -> (u64, u64)
+    {
+        let value = |stats: &Option<ScopeStats>| stats.as_ref().map_or(0, |stats| metric.of(stats));
+        (value(&self.before), value(&self.after))
+    }
+
+    /// Growth of `metric`, positive if it got slower or is called more often
+    #[must_use]
+    pub fn delta(&self, metric: Metric) -> i128
+    {
+        let (before, after) = self.values(metric);
+        i128::from(after) - i128::from(before)
+    }
+
+    /// Growth of `metric` relative to before, `None` if it was zero before
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn relative(&self, metric: Metric) -> Option<f64>
+    {
+        let (before, _) = self.values(metric);
+        (before > 0).then(|| self.delta(metric) as f64 / before as f64)
+    }
+
+    /// Whether `metric` grew by more than `percent` and by at least `min`, new scopes only have to grow by `min`
+    /// `min` is a number of calls for [`Metric::Count`], nanoseconds otherwise
+    #[must_use]
+    pub fn regressed(&self, metric: Metric, percent: f64, min: u64) -> bool
+    {
+        let delta = self.delta(metric);
+        delta > 0 && delta >= i128::from(min) && self.relative(metric).is_none_or(|relative| relative * 100.0 > percent)
+    }
+}
+
+/// Changes of every scope name from `before` to `after`, the biggest regression of `metric` first
+#[must_use]
+pub fn diff(before: &Trace, after: &Trace, metric: Metric) -> Vec<ScopeDiff>
+{
+    let mut scopes: BTreeMap<String, ScopeDiff> = BTreeMap::new();
+    let before = before.scope_stats().into_iter().map(|stats| (stats, false));
+    let after = after.scope_stats().into_iter().map(|stats| (stats, true));
+    for (stats, is_after) in before.chain(after)
+    {
+        let scope = scopes.entry(stats.name.clone()).or_insert_with(|| ScopeDiff {
+            name: stats.name.clone(),
+            before: None,
+            after: None,
+        });
+        let side = if is_after { &mut scope.after } else { &mut scope.before };
+        *side = Some(stats);
+    }
+    let mut diffs: Vec<_> = scopes.into_values().collect();
+    diffs.sort_by_key(|diff| std::cmp::Reverse(diff.delta(metric)));
+    diffs
+}
@@ -0,0 +1,104 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+thread_local! {
+    /// Names this thread interned into the session with the id, only new names lock its table
+    static CACHE: RefCell<(u64, HashMap<Arc<str>, NameId>)> = RefCell::default();
+}
+
+/// Index of a runtime name in the string table of a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct NameId
+{
+    session: u64,
+    index: u32,
+}
+
+impl NameId
+{
+    pub(crate) const fn session(self) -> u64 { self.session }
+}
+
+/// Interns runtime names, each distinct name is only stored once
+pub(crate) struct StringTable
+{
+    session: u64,
+    ids: HashMap<Arc<str>, NameId>,
+    strings: Vec<Arc<str>>,
+}
+
+impl StringTable
+{
+    /// Table of the session with the id
+    pub(crate) fn new(session: u64) -> Self
+    {
+        Self {
+            session,
+            ids: HashMap::new(),
+            strings: Vec::new(),
+        }
+    }
+
+    /// Repeated names are only looked up, no allocation happens
+    fn intern(&mut self, name: &str) -> (NameId, Arc<str>)
+    {
+        if let Some((name, &id)) = self.ids.get_key_value(name)
+        {
+            return (id, name.clone());
+        }
+        let id = NameId {
+            session: self.session,
+            index: self.strings.len().try_into().expect("Too many interned names"),
+        };
+        let name: Arc<str> = name.into();
+        self.strings.push(name.clone());
+        self.ids.insert(name.clone(), id);
+        (id, name)
+    }
+
+    /// Snapshot of all names interned so far
+    pub(crate) fn strings(&self) -> Strings
+    {
+        Strings {
+            session: self.session,
+            strings: self.strings.clone(),
+        }
+    }
+}
+
+/// Interns `name` into the `table` of the session, the cache of this thread is looked at first
+pub(crate) fn intern(table: &Mutex<StringTable>, session: u64, name: &str) -> (NameId, Arc<str>)
+{
+    CACHE.with_borrow_mut(|(cached, ids)| {
+        if *cached != session
+        {
+            *cached = session;
+            ids.clear();
+        }
+        if let Some((name, &id)) = ids.get_key_value(name)
+        {
+            return (id, name.clone());
+        }
+        let (id, name) = table.lock().expect("Could not get access").intern(name);
+        ids.insert(name.clone(), id);
+        (id, name)
+    })
+}
+
+pub(crate) struct Strings
+{
+    session: u64,
+    strings: Vec<Arc<str>>,
+}
+
+impl Strings
+{
+    /// `None` for ids of another session
+    pub(crate) fn get(&self, id: NameId) -> Option<&str>
+    {
+        (id.session == self.session).then(|| self.strings.get(id.index as usize)).flatten().map(AsRef::as_ref)
+    }
+}
@@ -0,0 +1,109 @@
+use std::{io, path::Path};
+
+use serde_json::{Map, Value as JsonValue};
+
+use crate::{
+    BinaryTrace, ChromeTrace, Compression, Format, model::{Event, Text}, record_scope::MetaTrace, recording::Recording
+};
+
+/// A recording read back from a file, to inspect, filter or merge it and write it in another format
+/// Reads chrome json traces and the compact binary format, `.gz` or `.zst` compressed ones are decompressed
+pub struct Trace(pub(crate) Recording);
+
+impl Trace
+{
+    /// Reads the trace at `path`, the format is picked by its extension
+    ///
+    /// # Errors
+    /// Returns an Error if the file can not be read or is in a format that can not be read back
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self>
+    {
+        let path = path.as_ref();
+        match Format::from_path(path)
+        {
+            Format::Binary => BinaryTrace::open(path).map(Into::into),
+            Format::Json => Ok(Self(ChromeTrace::load(path)?.into_recording())),
+            format => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{format:?} files can not be read, only json and binary traces: {}", path.display()),
+            )),
+        }
+    }
+
+    /// Number of recorded events
+    #[must_use]
+    pub fn event_count(&self) -> usize { self.0.events.len() }
+
+    /// All events in order of their start
+    #[must_use]
+    pub fn events(&self) -> &[Event] { &self.0.events }
+
+    /// Keeps only the events `keep` returns true for
+    pub fn retain(&mut self, keep: impl FnMut(&Event) -> bool) { self.0.events.retain(keep); }
+
+    /// Metadata added with [`RecordScope::add_meta_data`](crate::RecordScope::add_meta_data) and the dropped events
+    #[must_use]
+    pub fn metadata(&self) -> &Map<String, JsonValue> { &self.0.extras }
+
+    /// Name given to `header`, the header itself otherwise
+    #[must_use]
+    pub fn process_name<'a>(&'a self, header: &'a str) -> &'a str { MetaTrace::process_name(&self.0.meta_traces, header) }
+
+    /// Name given to the thread, `Thread <id>` otherwise
+    #[must_use]
+    pub fn thread_name(&self, header: &str, thread: u64) -> String { MetaTrace::thread_name(&self.0.meta_traces, header, thread) }
+
+    /// Replaces every header of events and names with `rename(header)`
+    pub fn rename_headers(&mut self, rename: impl Fn(&str) -> String)
+    {
+        let rename = |header: &mut Text| *header = rename(header).into();
+        self.0.events.iter_mut().for_each(|event| rename(&mut event.header));
+        for meta_trace in &mut self.0.meta_traces
+        {
+            match meta_trace
+            {
+                MetaTrace::ProcessName(header, _) | MetaTrace::ThreadName(header, ..) => rename(header),
+            }
+        }
+    }
+
+    /// Adds the events and names of `other`, both start at the same time
+    /// Metadata of this trace wins over the one of `other`
+    pub fn merge(&mut self, other: Self)
+    {
+        let Recording { events, meta_traces, extras, .. } = other.0;
+        self.0.events.extend(events);
+        Event::sort(&mut self.0.events);
+        self.0.meta_traces.extend(meta_traces);
+        for (key, value) in extras
+        {
+            self.0.extras.entry(key).or_insert(value);
+        }
+    }
+
+    /// Writes the recording to `path` in the format and compression picked by its extension
+    ///
+    /// # Errors
+    /// Returns an Error if the file can not be written
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()>
+    {
+        let format = Format::from_path(path.as_ref());
+        let path = format.output_path(path.as_ref());
+        self.0.write(&path, format, Compression::from_path(&path))
+    }
+
+    /// Writes the recording to `path` as it is, in the given format and compression
+    ///
+    /// # Errors
+    /// Returns an Error if the file can not be written
+    pub fn write_as(&self, path: impl AsRef<Path>, format: Format, compression: Compression) -> io::Result<()>
+    {
+        self.0.write(path.as_ref(), format, compression)
+    }
+}
+
+/// Converts the trace at `input` into `output`, both in the format picked by their extension
+///
+/// # Errors
+/// Returns an Error if either file can not be accessed or the input can not be read back
+pub fn convert(input: impl AsRef<Path>, output: impl AsRef<Path>) -> io::Result<()> { Trace::open(input)?.write(output) }
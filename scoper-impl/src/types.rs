@@ -1,14 +1,40 @@
 use std::thread::{ThreadId, current};
 
-use scoper_base::{Info, InstantScopeSize, Value};
+use scoper_base::{Args, AsyncId, AsyncPhase, Info, InstantScopeSize, Value};
 
-use crate::{TimePoint, scopes::Start};
+use crate::{TimePoint, scopes::Start, strings::NameId};
 
+#[derive(Clone)]
 pub(super) enum TaggedData
 {
     Scope(Start),
     Counter(Value),
     Instant(InstantScopeSize),
+    Async(AsyncEvent),
+    Flow(FlowEvent),
+    Object(ObjectEvent),
+}
+
+#[derive(Clone)]
+pub(crate) struct AsyncEvent(pub AsyncPhase, pub AsyncId);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum FlowPhase
+{
+    Out,
+    Step,
+    In,
+}
+
+#[derive(Clone)]
+pub(crate) struct FlowEvent(pub FlowPhase, pub u64);
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ObjectEvent
+{
+    Created(u64),
+    Snapshot(u64, serde_json::Value),
+    Destroyed(u64),
 }
 
 impl From<Start> for TaggedData {
@@ -29,6 +55,22 @@ impl From<InstantScopeSize> for TaggedData {
     }
 }
 
+impl From<AsyncEvent> for TaggedData
+{
+    fn from(value: AsyncEvent) -> Self { Self::Async(value) }
+}
+
+impl From<FlowEvent> for TaggedData
+{
+    fn from(value: FlowEvent) -> Self { Self::Flow(value) }
+}
+
+impl From<ObjectEvent> for TaggedData
+{
+    fn from(value: ObjectEvent) -> Self { Self::Object(value) }
+}
+
+#[derive(Clone)]
 pub(super) struct Trace<Extra>(pub BaseInfo, pub Extra);
 pub(super) type TaggedTrace = Trace<TaggedData>;
 
@@ -39,11 +81,14 @@ impl<Extra: Into<TaggedData>> Trace<Extra>
     }
 }
 
+#[derive(Clone)]
 pub(super) struct BaseInfo
 {
     pub thread_id: ThreadId, //All trace types
     pub time_point: TimePoint,    //All trace types
     pub info: Info,          //static info
+    pub args: Args,          //runtime args
+    pub name: Option<NameId>, //runtime name, replaces the static one
 }
 
 impl BaseInfo
@@ -54,8 +99,14 @@ impl BaseInfo
             thread_id: current().id(),
             info,
             time_point,
+            args: Args::new(),
+            name: None,
         }
     }
 
     pub(crate) fn build_now(info: Info) -> Self { Self::build(info, TimePoint::now()) }
+
+    pub(crate) fn with_args(self, args: Args) -> Self { Self { args, ..self } }
+
+    pub(crate) fn with_name(self, name: Option<NameId>) -> Self { Self { name, ..self } }
 }
@@ -7,29 +7,71 @@ pub mod macros
     #[macro_export]
     macro_rules! record_scope
     {
-        ($header: expr, $name: expr) => {};
-        ($name: expr) => {};
+        (dyn $name: expr $(, $key: ident = $value: expr)* $(; $($flow: ident = $id: expr),+)?) => {};
+        ($header: expr, dyn $name: expr $(, $key: ident = $value: expr)* $(; $($flow: ident = $id: expr),+)?) => {};
+        ($name: expr $(, $key: ident = $value: expr)* $(; $($flow: ident = $id: expr),+)?) => {};
+        ($header: expr, $name: expr $(, $key: ident = $value: expr)* $(; $($flow: ident = $id: expr),+)?) => {};
     }
 
     #[macro_export]
     macro_rules! record_value
     {
-        ($header: expr, $name: expr, $value: expr) =>{};
+        ($header: expr, dyn $name: expr, $value: expr $(, $key: ident = $arg: expr)*) =>{};
+        ($header: expr, $name: expr, $value: expr $(, $key: ident = $arg: expr)*) =>{};
     }
 
     #[macro_export]
     macro_rules! record_instant
     {
-        ($header: expr, $name: expr, $scope_size: expr) => {};
+        (dyn $name: expr $(, $key: ident = $value: expr)*) => {};
+        (dyn $name: expr, $scope_size: expr $(, $key: ident = $value: expr)*) => {};
+        ($header: expr, dyn $name: expr, $scope_size: expr $(, $key: ident = $value: expr)*) => {};
+        ($name: expr $(, $key: ident = $value: expr)+) => {};
+        ($name: expr, $scope_size: expr $(, $key: ident = $value: expr)+) => {};
+        ($header: expr, $name: expr, $scope_size: expr $(, $key: ident = $value: expr)*) => {};
         ($name: expr, $scope_size: expr) => {};
         ($name: expr) => {};
     }
 
+    #[macro_export]
+    macro_rules! record_async
+    {
+        ($name: expr, $id: expr $(, $key: ident = $value: expr)+ $(; category = $category: expr)?) => { $crate::AsyncSpan::disabled() };
+        ($header: expr, $name: expr, $id: expr $(, $key: ident = $value: expr)*; category = $category: expr) => { $crate::AsyncSpan::disabled() };
+        ($header: expr, $name: expr, $id: expr $(, $key: ident = $value: expr)*) => { $crate::AsyncSpan::disabled() };
+        ($name: expr, $id: expr $(; category = $category: expr)?) => { $crate::AsyncSpan::disabled() };
+    }
+
+    #[macro_export]
+    macro_rules! record_flow
+    {
+        ($header: expr, $name: expr) => { $crate::Flow::disabled() };
+        ($name: expr) => { $crate::Flow::disabled() };
+    }
+
+    #[macro_export]
+    macro_rules! trace_info
+    {
+        ($name: expr) => {
+            scoper::TraceInfo::new($name)
+                .with_category($crate::macros::hidden_reexport::str_replace!(::std::module_path!(), "::", ","))
+        };
+    }
+
     pub use record_scope;
     pub use record_value;
     pub use record_instant;
+    pub use record_async;
+    pub use record_flow;
+    pub use trace_info;
+
+    pub use scoper_attr::{TracedObject, record};
 
-    pub use scoper_attr::record;
+    #[doc(hidden)]
+    pub mod hidden_reexport
+    {
+        pub use const_format::str_replace;
+    }
 }
 
 //() for not constructable
@@ -41,6 +83,13 @@ impl Scope
     {
         Self(())
     }
+
+    pub fn start_named<N>(_info: Info, _name: N) -> Self
+    {
+        Self(())
+    }
+
+    pub fn arg<V>(&self, _key: &'static str, _value: V) {}
 }
 
 impl Drop for Scope
@@ -48,6 +97,119 @@ impl Drop for Scope
     fn drop(&mut self) {}
 }
 
+pub fn record_arg<V>(_key: &'static str, _value: V) {}
+
 pub fn record_custom_instant(_info: Info, _scope_size: InstantScopeSize) {}
+pub fn record_custom_instant_with_args(_info: Info, _scope_size: InstantScopeSize, _args: Args) {}
+pub fn record_custom_instant_named(_info: Info, _name: &str, _scope_size: InstantScopeSize, _args: Args) {}
 pub fn record_custom_scope(_info: Info, _start: Instant, _end: Instant) {}
 pub fn record_custom_value(_info: Info, _value: Value) {}
+pub fn record_custom_value_with_args(_info: Info, _value: Value, _args: Args) {}
+pub fn record_custom_value_named(_info: Info, _name: &str, _value: Value, _args: Args) {}
+
+/// Nothing is recorded, so there is nothing to dump
+///
+/// # Errors
+/// Never
+pub fn dump_flight_recorder(_path: impl AsRef<std::path::Path>) -> std::io::Result<()> { Ok(()) }
+
+pub struct AsyncSpan(());
+
+impl AsyncSpan
+{
+    #[must_use]
+    pub fn begin(_info: Info, _id: impl Into<AsyncId>) -> Self
+    {
+        Self(())
+    }
+
+    #[must_use]
+    pub fn begin_with_args(_info: Info, _id: impl Into<AsyncId>, _args: Args) -> Self
+    {
+        Self(())
+    }
+
+    #[doc(hidden)]
+    pub const fn disabled() -> Self
+    {
+        Self(())
+    }
+
+    pub fn step(&self, _info: Info) {}
+
+    pub fn step_with_args(&self, _info: Info, _args: Args) {}
+
+    pub fn end(self) {}
+}
+
+pub fn record_custom_async(_info: Info, _id: AsyncId, _phase: AsyncPhase) {}
+
+#[derive(Clone, Copy)]
+pub struct Flow(());
+
+impl Flow
+{
+    pub fn new(_info: Info) -> Self
+    {
+        Self(())
+    }
+
+    pub const fn with_id(_info: Info, _id: u64) -> Self
+    {
+        Self(())
+    }
+
+    #[doc(hidden)]
+    pub const fn disabled() -> Self
+    {
+        Self(())
+    }
+}
+
+pub fn flow_out(_flow: Flow) {}
+pub fn flow_step(_flow: Flow) {}
+pub fn flow_in(_flow: Flow) {}
+
+pub trait TracedObject {}
+
+impl<T> TracedObject for T {}
+
+pub struct Traced<T>(T);
+
+impl<T: TracedObject> Traced<T>
+{
+    pub fn new(value: T) -> Self
+    {
+        Self(value)
+    }
+}
+
+impl<T> Traced<T>
+{
+    pub fn with_info(_info: Info, value: T) -> Self
+    {
+        Self(value)
+    }
+
+    pub fn snapshot(&self) {}
+
+    pub fn snapshot_with<S: ?Sized>(&self, _state: &S) {}
+}
+
+impl<T> std::ops::Deref for Traced<T>
+{
+    type Target = T;
+
+    fn deref(&self) -> &T
+    {
+        &self.0
+    }
+}
+
+impl<T> std::ops::DerefMut for Traced<T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        &mut self.0
+    }
+}
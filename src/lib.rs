@@ -17,13 +17,7 @@ mod test
     fn wait_30_ms()
     {
         use crate::{Info, Scope, TraceInfo};
-        static SCOPE_INFO: Info = &TraceInfo {
-            //event_typ: EventType::Scope,
-            name: "30 Millis",
-            category: "inlinetest",
-            header: "30 Millis",
-            args: "",
-        };
+        static SCOPE_INFO: Info = &TraceInfo::new("30 Millis").with_category("inlinetest").with_header("30 Millis");
 
         let _profiling_scope = Scope::start(SCOPE_INFO);
         let value = 0.8;
@@ -31,12 +25,25 @@ mod test
         sleep(Duration::from_millis(30));
     }
 
+    fn trace_events(path: &str) -> Vec<serde_json::Value>
+    {
+        let file = std::fs::File::open(path).unwrap();
+        let mut data: serde_json::Value = serde_json::from_reader(file).unwrap();
+        data["traceEvents"].take().as_array().unwrap().clone()
+    }
+
     #[record]
     fn wait_30_ms_macro() { sleep(Duration::from_millis(30)); }
 
     #[record(custom header)]
     fn wait_30_ms_custom_header() { sleep(Duration::from_millis(30)); }
 
+    #[record(bytes = data.len())]
+    fn consume(data: &[u8]) { crate::record_arg("first", data[0]); }
+
+    #[record(Jobs, flow_in = job)]
+    fn execute_job(job: crate::Flow) { sleep(Duration::from_millis(5)); }
+
     #[test]
     fn basic_test_explicit_drop()
     {
@@ -97,8 +104,10 @@ mod test
             wait_30_ms();
         }
 
+        let session = record.session();
         std::thread::scope(|s| {
             s.spawn(|| {
+                let _session = session.enter();
                 sleep(Duration::from_millis(20));
                 record.set_starting_time();
                 for _ in 0..10
@@ -109,6 +118,7 @@ mod test
                 }
             });
             s.spawn(|| {
+                let _session = session.enter();
                 for _ in 0..10
                 {
                     record_scope!("Thread D");
@@ -120,6 +130,7 @@ mod test
         record_instant!("First join", InstantScopeSize::Process);
         std::thread::scope(|s| {
             s.spawn(|| {
+                let _session = session.enter();
                 for _ in 0..10
                 {
                     record_scope!("Thread E");
@@ -132,4 +143,1241 @@ mod test
             .add_meta_data("test".to_string(), &String::from("SomeExtraInfoHere"))
             .ok();
     }
+
+    #[test]
+    fn parallel_sessions_test()
+    {
+        fn names_in(path: &str) -> Vec<String>
+        {
+            trace_events(path)
+                .iter()
+                .map(|event| event["name"].as_str().unwrap().to_string())
+                .collect()
+        }
+
+        std::fs::create_dir_all("results").unwrap();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let _record = RecordScope::start(Path::new("results/parallel_a.json"));
+                for _ in 0..5
+                {
+                    record_scope!("Session A");
+                    sleep(Duration::from_millis(5));
+                }
+            });
+            s.spawn(|| {
+                let _record = RecordScope::start(Path::new("results/parallel_b.json"));
+                for _ in 0..5
+                {
+                    record_scope!("Session B");
+                    sleep(Duration::from_millis(5));
+                }
+            });
+        });
+
+        let a = names_in("results/parallel_a.json");
+        let b = names_in("results/parallel_b.json");
+        assert_eq!(a.len(), 5);
+        assert_eq!(b.len(), 5);
+        assert!(a.iter().all(|name| name == "Session A"));
+        assert!(b.iter().all(|name| name == "Session B"));
+    }
+
+    #[test]
+    fn moved_record_test()
+    {
+        std::fs::create_dir_all("results").unwrap();
+        {
+            let _outer = RecordScope::start(Path::new("results/moved_outer.json"));
+            let inner = RecordScope::start(Path::new("results/moved_inner.json"));
+            record_instant!("before", InstantScopeSize::Thread);
+            std::thread::spawn(move || drop(inner)).join().unwrap();
+            record_instant!("after", InstantScopeSize::Thread);
+        }
+
+        let names = |path| trace_events(path).iter().map(|event| event["name"].to_string()).collect::<Vec<_>>();
+        assert_eq!(names("results/moved_inner.json"), ["\"before\""]);
+        assert_eq!(names("results/moved_outer.json"), ["\"after\""]);
+    }
+
+    #[test]
+    fn async_test()
+    {
+        std::fs::create_dir_all("results").unwrap();
+        {
+            let record = RecordScope::start(Path::new("results/async_test.json"));
+            let session = record.session();
+            let request = record_async!("Requests", "request", 42);
+            let upload = record_async!("upload", 7, size = 128_u64; category = "network");
+            upload.step_with_args(const { &crate::TraceInfo::new("upload").with_category("network") }, vec![("sent", 64_u64.into())]);
+            upload.end();
+            sleep(Duration::from_millis(5));
+            std::thread::scope(|s| {
+                s.spawn(move || {
+                    let _session = session.enter();
+                    record_scope!("handle request");
+                    sleep(Duration::from_millis(5));
+                    request.end();
+                });
+            });
+        }
+
+        let phases: Vec<_> = trace_events("results/async_test.json")
+            .into_iter()
+            .filter(|event| event["name"] == "request")
+            .map(|event| (event["ph"].as_str().unwrap().to_string(), event["id"].clone(), event["pid"].clone()))
+            .collect();
+        assert_eq!(phases.len(), 2);
+        assert_eq!(phases[0].0, "b");
+        assert_eq!(phases[1].0, "e");
+        assert_eq!(phases[0].1, "0x2a");
+        assert_eq!(phases[0].1, phases[1].1);
+        assert_eq!(phases[0].2, "Requests");
+
+        let upload: Vec<_> = trace_events("results/async_test.json").into_iter().filter(|event| event["name"] == "upload").collect();
+        let phases: Vec<_> = upload.iter().map(|event| event["ph"].as_str().unwrap()).collect();
+        assert_eq!(phases, ["b", "n", "e"]);
+        assert!(upload.iter().all(|event| event["cat"] == "network" && event["id"] == "0x7"));
+        assert_eq!(upload[0]["args"]["size"], 128);
+        assert_eq!(upload[1]["args"]["sent"], 64);
+    }
+
+    #[test]
+    fn flow_test()
+    {
+        std::fs::create_dir_all("results").unwrap();
+        {
+            let record = RecordScope::start(Path::new("results/flow_test.json"));
+            let session = record.session();
+            let (sender, receiver) = std::sync::mpsc::channel();
+            std::thread::scope(|s| {
+                s.spawn(move || {
+                    let _session = session.enter();
+                    for job in receiver
+                    {
+                        execute_job(job);
+                    }
+                });
+                for _ in 0..3
+                {
+                    let job = record_flow!("Jobs", "job");
+                    record_scope!("Jobs", "submit"; flow_out = job);
+                    sender.send(job).unwrap();
+                    sleep(Duration::from_millis(2));
+                }
+                drop(sender);
+            });
+        }
+
+        let events = trace_events("results/flow_test.json");
+        let starts: Vec<_> = events.iter().filter(|event| event["ph"] == "s").collect();
+        let finishes: Vec<_> = events.iter().filter(|event| event["ph"] == "f").collect();
+        assert_eq!(starts.len(), 3);
+        assert_eq!(finishes.len(), 3);
+        for start in starts
+        {
+            let finish = finishes.iter().find(|finish| finish["id"] == start["id"]).unwrap();
+            assert_eq!(finish["bp"], "e");
+            assert_ne!(finish["tid"], start["tid"]);
+        }
+    }
+
+    #[derive(TracedObject, serde::Serialize)]
+    struct Texture
+    {
+        width: u32,
+        height: u32,
+    }
+
+    #[test]
+    fn object_test()
+    {
+        std::fs::create_dir_all("results").unwrap();
+        {
+            let _record = RecordScope::start(Path::new("results/object_test.json"));
+            let mut texture = crate::Traced::new(Texture { width: 64, height: 64 });
+            texture.snapshot();
+            texture.width = 128;
+            texture.snapshot_with(&texture.width);
+            sleep(Duration::from_millis(5));
+        }
+
+        let events: Vec<_> = trace_events("results/object_test.json")
+            .into_iter()
+            .filter(|event| event["name"] == "Texture")
+            .collect();
+        let phases: Vec<_> = events.iter().map(|event| event["ph"].as_str().unwrap()).collect();
+        assert_eq!(phases, ["N", "O", "O", "D"]);
+        assert!(events.iter().all(|event| event["id"] == events[0]["id"]));
+        assert_eq!(events[1]["args"]["snapshot"], serde_json::json!({"width": 64, "height": 64}));
+        assert_eq!(events[2]["args"]["snapshot"], 128);
+    }
+
+    #[test]
+    fn args_test()
+    {
+        static OUTER: crate::Info = &crate::TraceInfo::new("outer");
+        static INNER: crate::Info = &crate::TraceInfo::new("inner");
+
+        std::fs::create_dir_all("results").unwrap();
+        {
+            let _record = RecordScope::start(Path::new("results/args_test.json"));
+            let path = String::from("level.bin");
+            {
+                record_scope!("load", path = path.as_str(), size = 1024_u32);
+                consume(&[7, 8, 9]);
+            }
+            record_instant!("loaded", InstantScopeSize::Thread, ok = true);
+            record_value!("", "memory", 3_u32.into(), heap = 2_u32, stack = 1_u32);
+
+            let outer = crate::Scope::start(OUTER);
+            let inner = crate::Scope::start(INNER);
+            outer.arg("depth", 0_u32);
+            inner.arg("depth", 1_u32);
+        }
+
+        let events = trace_events("results/args_test.json");
+        let find = |name: &str| events.iter().find(|event| event["name"] == name).unwrap().clone();
+        assert_eq!(find("load")["args"], serde_json::json!({"path": "level.bin", "size": 1024}));
+        assert_eq!(find("consume")["args"], serde_json::json!({"bytes": 3, "first": 7}));
+        assert_eq!(find("loaded")["args"], serde_json::json!({"ok": true}));
+        assert_eq!(find("memory")["args"], serde_json::json!({"": 3, "heap": 2, "stack": 1}));
+        // Args of the outer scope do not end up on the open inner one
+        assert_eq!((find("outer")["args"]["depth"].as_u64(), find("inner")["args"]["depth"].as_u64()), (Some(0), Some(1)));
+    }
+
+    #[test]
+    fn dynamic_name_test()
+    {
+        std::fs::create_dir_all("results").unwrap();
+        {
+            let _record = RecordScope::start(Path::new("results/dynamic_name_test.json"));
+            for id in 0..3
+            {
+                let name = format!("shader {}", id % 2);
+                record_scope!("Shaders", dyn name, id = id);
+                record_instant!(dyn format!("compiled {id}"), InstantScopeSize::Thread);
+                record_value!("Shaders", dyn name, id.into());
+            }
+        }
+
+        let names: Vec<_> = trace_events("results/dynamic_name_test.json")
+            .iter()
+            .map(|event| (event["ph"].as_str().unwrap().to_string(), event["name"].as_str().unwrap().to_string()))
+            .collect();
+        let scopes: Vec<_> = names.iter().filter(|(ph, _)| ph == "X").map(|(_, name)| name.as_str()).collect();
+        let instants: Vec<_> = names.iter().filter(|(ph, _)| ph == "i").map(|(_, name)| name.as_str()).collect();
+        let counters: Vec<_> = names.iter().filter(|(ph, _)| ph == "C").map(|(_, name)| name.as_str()).collect();
+        assert_eq!(scopes, ["shader 0", "shader 1", "shader 0"]);
+        assert_eq!(instants, ["compiled 0", "compiled 1", "compiled 2"]);
+        assert_eq!(counters, ["shader 0", "shader 1", "shader 0"]);
+
+        // Closed under another recording than the one it was opened in
+        {
+            let _first = RecordScope::start(Path::new("results/dynamic_name_first_test.json"));
+            let scope = crate::Scope::start_named(const { &crate::TraceInfo::new("") }, "opened in first");
+            let _second = RecordScope::start(Path::new("results/dynamic_name_second_test.json"));
+            record_instant!(dyn "only in second", InstantScopeSize::Thread);
+            drop(scope);
+        }
+        let names: Vec<_> = trace_events("results/dynamic_name_second_test.json").iter().map(|event| event["name"].clone()).collect();
+        assert_eq!(names, ["opened in first", "only in second"]);
+    }
+
+    #[test]
+    fn trace_info_builder_test()
+    {
+        const INFO: crate::TraceInfo = trace_info!("load").with_header("Loading").with_args(r#"{"level": 1}"#);
+        assert_eq!(INFO.name, "load");
+        assert_eq!(INFO.category, "scoper,test");
+        assert_eq!(INFO.header, "Loading");
+        assert_eq!(INFO.args, r#"{"level": 1}"#);
+    }
+
+    #[test]
+    fn limits_test()
+    {
+        use crate::{BufferLimits, OverflowPolicy};
+
+        fn record(path: &str, policy: OverflowPolicy) -> (Vec<u64>, serde_json::Value)
+        {
+            let record = RecordScope::start_with_limits(path, BufferLimits::new(4, policy));
+            let flusher = record.session();
+            let handle = std::thread::spawn(move || {
+                let _session = flusher.enter();
+                for count in 0..10_u64
+                {
+                    record_value!("limits", "count", count.into());
+                }
+            });
+            if policy == OverflowPolicy::Block
+            {
+                while !handle.is_finished()
+                {
+                    record.flush_to_disk().unwrap();
+                    sleep(Duration::from_millis(1));
+                }
+            }
+            handle.join().unwrap();
+            drop(record);
+
+            let file = std::fs::File::open(path).unwrap();
+            let data: serde_json::Value = serde_json::from_reader(file).unwrap();
+            let counts = data["traceEvents"].as_array().unwrap().iter().map(|event| event["args"][""].as_u64().unwrap()).collect();
+            (counts, data["droppedEvents"].clone())
+        }
+
+        std::fs::create_dir_all("results").unwrap();
+
+        let (counts, dropped) = record("results/limits_newest_test.json", OverflowPolicy::DropNewest);
+        assert_eq!(counts, [0, 1, 2, 3]);
+        assert_eq!(dropped, serde_json::json!({"counters": 6}));
+
+        let (counts, dropped) = record("results/limits_oldest_test.json", OverflowPolicy::DropOldest);
+        assert_eq!(counts, [6, 7, 8, 9]);
+        assert_eq!(dropped, serde_json::json!({"counters": 6}));
+
+        let (counts, dropped) = record("results/limits_disk_test.json", OverflowPolicy::FlushToDisk);
+        assert_eq!(counts, (0..10).collect::<Vec<_>>());
+        assert!(dropped.is_null());
+        assert!(!Path::new("results/limits_disk_test.spill").exists());
+
+        let (counts, dropped) = record("results/limits_block_test.json", OverflowPolicy::Block);
+        assert_eq!(counts, (0..10).collect::<Vec<_>>());
+        assert!(dropped.is_null());
+
+        // The capacity is shared by all threads, entering again does not add room
+        let record = RecordScope::start_with_limits("results/limits_session_test.json", BufferLimits::new(4, OverflowPolicy::DropNewest));
+        let session = record.session();
+        std::thread::scope(|s| {
+            for _ in 0..3
+            {
+                let session = session.clone();
+                s.spawn(move || {
+                    let (_session, _again) = (session.enter(), session.enter());
+                    (0..3_u64).for_each(|count| record_value!("limits", "count", count.into()));
+                });
+            }
+        });
+        drop(record);
+        let data: serde_json::Value = serde_json::from_reader(std::fs::File::open("results/limits_session_test.json").unwrap()).unwrap();
+        assert_eq!(data["traceEvents"].as_array().unwrap().len(), 4);
+        assert_eq!(data["droppedEvents"], serde_json::json!({"counters": 5}));
+
+        // Dropping the oldest event frees room for events of other kinds and threads
+        let record = RecordScope::start_with_limits("results/limits_ring_test.json", BufferLimits::new(4, OverflowPolicy::DropOldest));
+        let session = record.session();
+        std::thread::spawn(move || {
+            let _session = session.enter();
+            (0..4_u64).for_each(|count| record_value!("limits", "count", count.into()));
+        })
+        .join()
+        .unwrap();
+        record_instant!("limits", "first", InstantScopeSize::Thread);
+        record_instant!("limits", "second", InstantScopeSize::Thread);
+        drop(record);
+        let data: serde_json::Value = serde_json::from_reader(std::fs::File::open("results/limits_ring_test.json").unwrap()).unwrap();
+        let events: Vec<_> = data["traceEvents"].as_array().unwrap().iter().map(|event| event["name"].to_string() + &event["args"][""].to_string()).collect();
+        assert_eq!(events, ["\"count\"2", "\"count\"3", "\"first\"null", "\"second\"null"]);
+        assert_eq!(data["droppedEvents"], serde_json::json!({"counters": 2}));
+    }
+
+    #[test]
+    fn flight_recorder_test()
+    {
+        use crate::{FlightWindow, dump_flight_recorder};
+
+        fn instants(path: &str) -> Vec<String>
+        {
+            trace_events(path).iter().filter(|event| event["ph"] == "i").map(|event| event["name"].as_str().unwrap().to_string()).collect()
+        }
+
+        std::fs::create_dir_all("results").unwrap();
+
+        let mut record = RecordScope::flight_recorder("results/flight_recorder_test.json", FlightWindow::Events(3));
+        record.add_meta_data("build".to_string(), &"nightly").unwrap();
+        for frame in 0..10
+        {
+            record_instant!(dyn format!("frame {frame}"));
+        }
+        dump_flight_recorder("results/flight_recorder_events_dump.json").unwrap();
+        assert_eq!(instants("results/flight_recorder_events_dump.json"), ["frame 7", "frame 8", "frame 9"]);
+        // Dumping keeps the window for the next dump and the file of the recorder
+        dump_flight_recorder("results/flight_recorder_events_dump.json").unwrap();
+        assert_eq!(instants("results/flight_recorder_events_dump.json"), ["frame 7", "frame 8", "frame 9"]);
+        let dump: serde_json::Value = serde_json::from_str(&std::fs::read_to_string("results/flight_recorder_events_dump.json").unwrap()).unwrap();
+        assert_eq!((&dump["droppedEvents"]["instants"], &dump["build"]), (&serde_json::json!(7), &serde_json::json!("nightly")));
+        drop(record);
+        assert_eq!(instants("results/flight_recorder_test.json"), ["frame 7", "frame 8", "frame 9"]);
+        assert!(dump_flight_recorder("results/flight_recorder_inactive_dump.json").is_err());
+
+        // The window holds the most recent events of all threads and kinds
+        let record = RecordScope::flight_recorder("results/flight_recorder_mixed_test.json", FlightWindow::Events(4));
+        let in_thread = |names: [&'static str; 2]| {
+            let session = record.session();
+            std::thread::spawn(move || {
+                let _session = session.enter();
+                record_instant!(dyn names[0], InstantScopeSize::Thread);
+                record_scope!(dyn names[1]);
+            })
+            .join()
+            .unwrap();
+        };
+        in_thread(["first old", "first scope"]);
+        record_value!("", "main old", 1_u64.into());
+        in_thread(["second new", "second scope"]);
+        record_value!("", "main new", 2_u64.into());
+        record_instant!("main last", InstantScopeSize::Thread);
+        dump_flight_recorder("results/flight_recorder_mixed_dump.json").unwrap();
+        let names: Vec<_> = trace_events("results/flight_recorder_mixed_dump.json").iter().filter(|event| event["ph"] != "M").map(|event| event["name"].clone()).collect();
+        assert_eq!(names, ["second new", "second scope", "main new", "main last"].map(serde_json::Value::from)[..]);
+        drop(record);
+
+        let record = RecordScope::flight_recorder("results/flight_recorder_test.json", FlightWindow::Duration(Duration::from_millis(50)));
+        record_instant!("old");
+        sleep(Duration::from_millis(100));
+        record_instant!("new");
+        dump_flight_recorder("results/flight_recorder_time_dump.json").unwrap();
+        assert_eq!(instants("results/flight_recorder_time_dump.json"), ["new"]);
+        drop(record);
+        assert_eq!(instants("results/flight_recorder_test.json"), ["new"]);
+    }
+
+    #[test]
+    fn perfetto_test()
+    {
+        // Only the fields the test looks at
+        #[derive(prost::Message)]
+        struct Trace
+        {
+            #[prost(message, repeated, tag = "1")]
+            packet: Vec<Packet>,
+        }
+
+        #[derive(prost::Message)]
+        struct Packet
+        {
+            #[prost(uint64, optional, tag = "8")]
+            timestamp: Option<u64>,
+            #[prost(message, optional, tag = "11")]
+            track_event: Option<TrackEvent>,
+            #[prost(message, optional, tag = "12")]
+            interned_data: Option<InternedData>,
+            #[prost(message, optional, tag = "60")]
+            track_descriptor: Option<TrackDescriptor>,
+        }
+
+        #[derive(prost::Message)]
+        struct TrackDescriptor
+        {
+            #[prost(uint64, optional, tag = "1")]
+            uuid: Option<u64>,
+            #[prost(string, optional, tag = "2")]
+            name: Option<String>,
+            #[prost(message, optional, tag = "3")]
+            process: Option<ProcessDescriptor>,
+            #[prost(uint64, optional, tag = "5")]
+            parent_uuid: Option<u64>,
+        }
+
+        #[derive(prost::Message)]
+        struct ProcessDescriptor
+        {
+            #[prost(string, optional, tag = "6")]
+            process_name: Option<String>,
+        }
+
+        #[derive(prost::Message)]
+        struct TrackEvent
+        {
+            #[prost(int32, optional, tag = "9")]
+            r#type: Option<i32>,
+            #[prost(uint64, optional, tag = "10")]
+            name_iid: Option<u64>,
+            #[prost(uint64, optional, tag = "11")]
+            track_uuid: Option<u64>,
+            #[prost(int64, optional, tag = "30")]
+            counter_value: Option<i64>,
+        }
+
+        #[derive(prost::Message)]
+        struct InternedData
+        {
+            #[prost(message, repeated, tag = "2")]
+            event_names: Vec<EventName>,
+        }
+
+        #[derive(prost::Message)]
+        struct EventName
+        {
+            #[prost(uint64, optional, tag = "1")]
+            iid: Option<u64>,
+            #[prost(string, optional, tag = "2")]
+            name: Option<String>,
+        }
+
+        std::fs::create_dir_all("results").unwrap();
+        let path = "results/perfetto_test.perfetto-trace";
+        let mut record = RecordScope::start(path);
+        record.name_thread(std::thread::current().id(), "Render", "Main".to_string());
+        {
+            record_scope!("Render", "outer");
+            {
+                record_scope!("Render", "inner");
+                record_instant!("Render", "marker", InstantScopeSize::Thread);
+                sleep(Duration::from_millis(2));
+            }
+            record_value!("Render", "queue", 3_u32.into());
+        }
+        drop(record);
+
+        let trace = <Trace as prost::Message>::decode(std::fs::read(path).unwrap().as_slice()).unwrap();
+        let descriptors: Vec<_> = trace.packet.iter().filter_map(|packet| packet.track_descriptor.as_ref()).collect();
+        assert!(descriptors.iter().any(|track| track.process.as_ref().and_then(|p| p.process_name.as_deref()) == Some("Render")));
+        assert!(descriptors.iter().any(|track| track.name.as_deref() == Some("Main")));
+        let queue = descriptors.iter().find(|track| track.name.as_deref() == Some("queue")).unwrap().uuid;
+        // Unique uuids, the process track comes first and is the parent of the others
+        assert_eq!(descriptors.iter().map(|track| track.uuid).collect::<std::collections::HashSet<_>>().len(), descriptors.len());
+        assert!(descriptors[1..].iter().all(|track| track.parent_uuid == descriptors[0].uuid));
+
+        let names: std::collections::HashMap<_, _> = trace
+            .packet
+            .iter()
+            .filter_map(|packet| packet.interned_data.as_ref())
+            .flat_map(|data| &data.event_names)
+            .map(|name| (name.iid.unwrap(), name.name.clone().unwrap()))
+            .collect();
+
+        // Slices as (type, name) in timestamp order, nesting has to survive
+        let events: Vec<_> = trace.packet.iter().filter(|packet| packet.track_event.is_some()).collect();
+        assert!(events.windows(2).all(|pair| pair[0].timestamp <= pair[1].timestamp));
+        let slices: Vec<_> = events
+            .iter()
+            .filter_map(|packet| packet.track_event.as_ref().filter(|event| event.track_uuid != queue))
+            .map(|event| (event.r#type.unwrap(), event.name_iid.map(|iid| names[&iid].as_str())))
+            .collect();
+        assert_eq!(slices, [(1, Some("outer")), (1, Some("inner")), (3, Some("marker")), (2, None), (2, None)]);
+
+        let counters = events.iter().filter_map(|packet| packet.track_event.as_ref()).filter(|event| event.track_uuid == queue);
+        assert_eq!(counters.map(|event| event.counter_value).collect::<Vec<_>>(), [Some(3)]);
+    }
+
+    #[test]
+    fn firefox_test()
+    {
+        std::fs::create_dir_all("results").unwrap();
+        let path = "results/firefox_test.firefox.json";
+        let mut record = RecordScope::start(path);
+        record.name_thread(std::thread::current().id(), "Render", "Main".to_string());
+        {
+            record_scope!("Render", "outer");
+            record_instant!("Render", "marker", InstantScopeSize::Thread);
+            record_value!("Render", "queue", 3_u32.into());
+            sleep(Duration::from_millis(2));
+        }
+        drop(record);
+
+        let file = std::fs::File::open(path).unwrap();
+        let profile: serde_json::Value = serde_json::from_reader(file).unwrap();
+        let thread = &profile["threads"][0];
+        assert_eq!(thread["name"], "Main");
+        assert_eq!(thread["processName"], "Render");
+
+        let markers = &thread["markers"];
+        let strings = thread["stringArray"].as_array().unwrap();
+        let names: Vec<_> = markers["name"].as_array().unwrap().iter().map(|index| &strings[usize::try_from(index.as_u64().unwrap()).unwrap()]).collect();
+        assert_eq!(names, ["outer", "marker"]);
+        // Interval and instant phases
+        assert_eq!(markers["phase"], serde_json::json!([1, 0]));
+        let (start, end) = (markers["startTime"][0].as_f64().unwrap(), markers["endTime"][0].as_f64().unwrap());
+        assert!(end - start >= 2.0);
+
+        let counter = &profile["counters"][0];
+        assert_eq!(counter["name"], "queue");
+        assert_eq!(counter["samples"]["count"], serde_json::json!([3.0]));
+    }
+
+    #[test]
+    fn speedscope_test()
+    {
+        std::fs::create_dir_all("results").unwrap();
+        let path = "results/speedscope_test.speedscope.json";
+        let record = RecordScope::start(path);
+        {
+            record_scope!("outer");
+            {
+                record_scope!("inner");
+                {
+                    record_scope!("leaf");
+                    sleep(Duration::from_millis(1));
+                }
+            }
+            {
+                record_scope!("sibling");
+                sleep(Duration::from_millis(1));
+            }
+        }
+        let session = record.session();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let _session = session.enter();
+                record_scope!("worker");
+                sleep(Duration::from_millis(1));
+            });
+        });
+        drop(record);
+
+        let file = std::fs::File::open(path).unwrap();
+        let speedscope: serde_json::Value = serde_json::from_reader(file).unwrap();
+        let frames = speedscope["shared"]["frames"].as_array().unwrap();
+        let profiles = speedscope["profiles"].as_array().unwrap();
+        assert_eq!(profiles.len(), 2);
+
+        let steps: Vec<Vec<(String, String)>> = profiles
+            .iter()
+            .map(|profile| {
+                let mut open = Vec::new();
+                let mut last = 0;
+                profile["events"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|event| {
+                        let frame = event["frame"].as_u64().unwrap();
+                        let at = event["at"].as_u64().unwrap();
+                        assert!(at >= last);
+                        last = at;
+                        // Closes have to match the innermost open frame
+                        if event["type"] == "O"
+                        {
+                            open.push(frame);
+                        }
+                        else
+                        {
+                            assert_eq!(open.pop(), Some(frame));
+                        }
+                        let name = &frames[usize::try_from(frame).unwrap()]["name"];
+                        (event["type"].as_str().unwrap().to_string(), name.as_str().unwrap().to_string())
+                    })
+                    .collect()
+            })
+            .collect();
+        let expected = [
+            ("O", "outer"),
+            ("O", "inner"),
+            ("O", "leaf"),
+            ("C", "leaf"),
+            ("C", "inner"),
+            ("O", "sibling"),
+            ("C", "sibling"),
+            ("C", "outer"),
+        ];
+        let expected: Vec<_> = expected.iter().map(|&(kind, name)| (kind.to_string(), name.to_string())).collect();
+        assert!(steps.contains(&expected));
+        assert!(steps.contains(&vec![("O".to_string(), "worker".to_string()), ("C".to_string(), "worker".to_string())]));
+    }
+
+    #[test]
+    fn flamegraph_test()
+    {
+        std::fs::create_dir_all("results").unwrap();
+        let mut record = RecordScope::start("results/flamegraph_test.json");
+        record.add_output("results/flamegraph_test.folded");
+        record.add_output("results/flamegraph_test.svg");
+        {
+            record_scope!("outer");
+            {
+                record_scope!("inner");
+                sleep(Duration::from_millis(4));
+            }
+            sleep(Duration::from_millis(2));
+        }
+        drop(record);
+
+        let folded = std::fs::read_to_string("results/flamegraph_test.folded").unwrap();
+        let micros = |stack: &str| -> u64 {
+            folded
+                .lines()
+                .find_map(|line| line.strip_prefix(stack)?.strip_prefix(' ')?.parse().ok())
+                .unwrap()
+        };
+        // Weighted by self time
+        assert!(micros("outer;inner") >= 4000);
+        assert!((2000..micros("outer;inner")).contains(&micros("outer")));
+
+        let svg = std::fs::read_to_string("results/flamegraph_test.svg").unwrap();
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains("<title>outer ("));
+        assert!(svg.contains("<title>inner ("));
+        assert!(std::fs::exists("results/flamegraph_test.json").unwrap());
+    }
+
+    #[test]
+    fn pprof_test()
+    {
+        // Only the fields the test looks at
+        #[derive(prost::Message)]
+        struct Profile
+        {
+            #[prost(message, repeated, tag = "1")]
+            sample_type: Vec<ValueType>,
+            #[prost(message, repeated, tag = "2")]
+            sample: Vec<Sample>,
+            #[prost(message, repeated, tag = "5")]
+            function: Vec<Function>,
+            #[prost(string, repeated, tag = "6")]
+            string_table: Vec<String>,
+        }
+
+        #[derive(prost::Message)]
+        struct ValueType
+        {
+            #[prost(int64, tag = "1")]
+            r#type: i64,
+        }
+
+        #[derive(prost::Message)]
+        struct Sample
+        {
+            #[prost(uint64, repeated, tag = "1")]
+            location_id: Vec<u64>,
+            #[prost(int64, repeated, tag = "2")]
+            value: Vec<i64>,
+            #[prost(message, repeated, tag = "3")]
+            label: Vec<Label>,
+        }
+
+        #[derive(prost::Message)]
+        struct Label
+        {
+            #[prost(int64, tag = "1")]
+            key: i64,
+            #[prost(int64, tag = "2")]
+            str: i64,
+        }
+
+        #[derive(prost::Message)]
+        struct Function
+        {
+            #[prost(uint64, tag = "1")]
+            id: u64,
+            #[prost(int64, tag = "2")]
+            name: i64,
+        }
+
+        std::fs::create_dir_all("results").unwrap();
+        let path = "results/pprof_test.pprof";
+        let mut record = RecordScope::start(path);
+        record.name_thread(std::thread::current().id(), "Render", "Main".to_string());
+        for _ in 0..2
+        {
+            record_scope!("Render", "outer");
+            {
+                record_scope!("Render", "inner");
+                sleep(Duration::from_millis(2));
+            }
+            sleep(Duration::from_millis(1));
+        }
+        drop(record);
+
+        let profile = <Profile as prost::Message>::decode(std::fs::read(path).unwrap().as_slice()).unwrap();
+        let string = |index: i64| profile.string_table[usize::try_from(index).unwrap()].as_str();
+        let types: Vec<_> = profile.sample_type.iter().map(|value_type| string(value_type.r#type)).collect();
+        assert_eq!(types, ["wall_inclusive", "wall_exclusive"]);
+
+        // One location per unique scope, equal stacks are merged
+        let function = |id: u64| string(profile.function.iter().find(|function| function.id == id).unwrap().name);
+        let stacks: Vec<Vec<_>> = profile.sample.iter().map(|sample| sample.location_id.iter().map(|&id| function(id)).collect()).collect();
+        assert_eq!(profile.function.len(), 2);
+        assert_eq!(stacks, [vec!["outer"], vec!["inner", "outer"]]);
+
+        let (outer, inner) = (&profile.sample[0], &profile.sample[1]);
+        assert!(inner.value[0] >= 4_000_000);
+        assert_eq!(inner.value[0], inner.value[1]);
+        assert!(outer.value[0] >= inner.value[0] + 2_000_000);
+        assert_eq!(outer.value[0] - outer.value[1], inner.value[0]);
+
+        let labels: Vec<_> = outer.label.iter().map(|label| (string(label.key), string(label.str))).collect();
+        assert_eq!(labels, [("header", "Render"), ("thread", "Main")]);
+    }
+
+    #[test]
+    fn callgrind_test()
+    {
+        std::fs::create_dir_all("results").unwrap();
+        let path = "results/callgrind.out.scoper_test";
+        let record = RecordScope::start(path);
+        {
+            record_scope!("outer");
+            for _ in 0..2
+            {
+                record_scope!("inner");
+                sleep(Duration::from_millis(2));
+            }
+            sleep(Duration::from_millis(1));
+        }
+        drop(record);
+
+        let file = std::fs::read_to_string(path).unwrap();
+        assert!(file.starts_with("# callgrind format\n"));
+        assert!(file.contains("\nevents: us\n"));
+        // Category is the module path, the first use of a name defines its id
+        let blocks: Vec<Vec<&str>> = file.split("\n\n").skip(1).map(|block| block.lines().collect()).collect();
+        assert_eq!(blocks[0][..3], ["ob=(1) scoper::test", "fl=(1) scoper::test", "fn=(1) inner"]);
+        assert_eq!(blocks[1][..3], ["ob=(1)", "fl=(1)", "fn=(2) outer"]);
+        assert_eq!(blocks[1][4..6], ["cfn=(1)", "calls=2 0"]);
+
+        let cost = |line: &str| -> u64 { line.strip_prefix("0 ").unwrap().parse().unwrap() };
+        let inclusive = cost(blocks[1][6]);
+        assert!(inclusive >= 4000);
+        assert_eq!(cost(blocks[0][3]), inclusive);
+        assert!(cost(blocks[1][3]) >= 1000);
+    }
+
+    #[test]
+    fn otlp_test()
+    {
+        use std::{
+            io::{BufRead, BufReader, Read, Write},
+            net::TcpListener,
+        };
+
+        // Stand-in for an OTLP/HTTP receiver
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let receiver = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for stream in listener.incoming().take(2)
+            {
+                let mut stream = BufReader::new(stream.unwrap());
+                let mut lines = Vec::new();
+                loop
+                {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).unwrap();
+                    if line.trim_end().is_empty()
+                    {
+                        break;
+                    }
+                    lines.push(line.trim_end().to_string());
+                }
+                let length = lines.iter().find_map(|line| line.strip_prefix("Content-Length: ")).unwrap().parse().unwrap();
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).unwrap();
+                stream.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+                let path = lines[0].split_whitespace().nth(1).unwrap().to_string();
+                requests.push((path, serde_json::from_slice::<serde_json::Value>(&body).unwrap()));
+            }
+            requests
+        });
+
+        std::fs::create_dir_all("results").unwrap();
+        let path = "results/otlp_test.otlp.json";
+        let mut record = RecordScope::start(path);
+        record.send_otlp(endpoint);
+        record.final_header("Render", "Renderer".to_string());
+        {
+            record_scope!("Render", "outer");
+            record_instant!("Render", "marker", InstantScopeSize::Thread);
+            {
+                record_scope!("Render", "inner", frame = 7_u32);
+                sleep(Duration::from_millis(1));
+            }
+            record_value!("Render", "queue", 3_u32.into());
+        }
+        drop(record);
+
+        let requests = receiver.join().unwrap();
+        assert_eq!(requests[0].0, "/v1/traces");
+        assert_eq!(requests[1].0, "/v1/metrics");
+        let file = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<serde_json::Value> = file.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines, [requests[0].1.clone(), requests[1].1.clone()]);
+
+        let resource = &lines[0]["resourceSpans"][0];
+        assert_eq!(resource["resource"]["attributes"][0], serde_json::json!({"key": "service.name", "value": {"stringValue": "Renderer"}}));
+        let scope = &resource["scopeSpans"][0];
+        assert_eq!(scope["scope"]["name"], "scoper::test");
+        let (outer, inner) = (&scope["spans"][0], &scope["spans"][1]);
+        assert_eq!(outer["name"], "outer");
+        assert_eq!(inner["name"], "inner");
+        assert!(outer.get("parentSpanId").is_none());
+        assert_eq!(inner["parentSpanId"], outer["spanId"]);
+        assert_eq!(inner["traceId"], outer["traceId"]);
+        assert!(inner["attributes"].as_array().unwrap().contains(&serde_json::json!({"key": "frame", "value": {"intValue": "7"}})));
+        assert_eq!(outer["events"][0]["name"], "marker");
+        assert_eq!(inner["events"], serde_json::json!([]));
+
+        let metric = &lines[1]["resourceMetrics"][0]["scopeMetrics"][0]["metrics"][0];
+        assert_eq!(metric["name"], "queue");
+        assert_eq!(metric["gauge"]["dataPoints"][0]["asInt"], "3");
+    }
+
+    #[test]
+    fn compression_test()
+    {
+        use std::io::Read;
+
+        fn names(mut reader: impl Read) -> Vec<serde_json::Value>
+        {
+            let mut data = String::new();
+            reader.read_to_string(&mut data).unwrap();
+            let data: serde_json::Value = serde_json::from_str(&data).unwrap();
+            data["traceEvents"].as_array().unwrap().iter().map(|event| event["name"].clone()).collect()
+        }
+
+        std::fs::create_dir_all("results").unwrap();
+        let mut record = RecordScope::start("results/compression_test.json.gz");
+        record.add_output("results/compression_test.zst");
+        {
+            record_scope!("compressed");
+        }
+        drop(record);
+
+        // The extension of the compression stays last
+        assert!(!std::fs::exists("results/compression_test.json.json").unwrap());
+        let gzip = std::fs::File::open("results/compression_test.json.gz").unwrap();
+        assert!(names(flate2::read::GzDecoder::new(gzip)).contains(&serde_json::json!("compressed")));
+        let zstd = std::fs::File::open("results/compression_test.json.zst").unwrap();
+        assert!(names(zstd::Decoder::new(zstd).unwrap()).contains(&serde_json::json!("compressed")));
+    }
+
+    #[test]
+    fn streaming_test()
+    {
+        std::fs::create_dir_all("results").unwrap();
+        let path = "results/streaming_test.json";
+        // Small chunks to get many runs
+        let mut record = RecordScope::start_streaming(path, 8);
+        record.add_meta_data("streamed".to_string(), &true).unwrap();
+        let session = record.session();
+        std::thread::scope(|s| {
+            for thread in 0..4
+            {
+                let session = session.clone();
+                s.spawn(move || {
+                    let _session = session.enter();
+                    for index in 0..100_u32
+                    {
+                        record_scope!("step");
+                        record_value!("", "progress", (thread * 100 + index).into());
+                    }
+                });
+            }
+        });
+        drop(record);
+
+        assert!(!std::fs::exists("results/streaming_test.spill").unwrap());
+        let file = std::fs::File::open(path).unwrap();
+        let data: serde_json::Value = serde_json::from_reader(file).unwrap();
+        assert_eq!(data["streamed"], true);
+        let events = data["traceEvents"].as_array().unwrap();
+        let count = |name: &str| events.iter().filter(|event| event["name"] == name).count();
+        assert_eq!(count("step"), 400);
+        assert_eq!(count("progress"), 400);
+        // Merged back in order
+        let times: Vec<_> = events.iter().filter(|event| event["ph"] != "M").map(|event| event["ts"].as_u64().unwrap()).collect();
+        assert!(times.is_sorted());
+    }
+
+    #[test]
+    fn binary_test()
+    {
+        std::fs::create_dir_all("results").unwrap();
+        let mut record = RecordScope::start("results/binary_test.scoper");
+        record.add_output("results/binary_test.json");
+        record.name_thread(std::thread::current().id(), "Render", "Main".to_string());
+        record.add_meta_data("build".to_string(), &"release").unwrap();
+        for frame in 0..50_u32
+        {
+            record_scope!("Render", "frame", frame = frame);
+            let request = record_async!("Render", "upload", u64::from(frame));
+            record_value!("Render", "delta", (-i64::from(frame)).into(), load = 0.5);
+            record_instant!("Render", "present", InstantScopeSize::Process);
+            {
+                let job = record_flow!("Render", "job");
+                record_scope!("Render", "submit"; flow_out = job);
+            }
+            request.end();
+        }
+        drop(record);
+
+        let trace = crate::BinaryTrace::open("results/binary_test.scoper").unwrap();
+        assert_eq!(trace.event_count(), 50 * 7);
+        crate::convert("results/binary_test.scoper", "results/binary_converted.json.gz").unwrap();
+        trace.write("results/binary_converted.speedscope.json").unwrap();
+
+        // Converting after the fact gives the same trace as writing it directly
+        let direct: serde_json::Value = serde_json::from_reader(std::fs::File::open("results/binary_test.json").unwrap()).unwrap();
+        let gzip = flate2::read::GzDecoder::new(std::fs::File::open("results/binary_converted.json.gz").unwrap());
+        let converted: serde_json::Value = serde_json::from_reader(gzip).unwrap();
+        assert_eq!(converted, direct);
+        let binary = std::fs::metadata("results/binary_test.scoper").unwrap().len();
+        let json = std::fs::metadata("results/binary_test.json").unwrap().len();
+        assert!(binary * 4 < json);
+
+        // Streamed recordings are encoded run by run as they are read back from the spill file
+        let record = RecordScope::start_streaming("results/binary_streaming_test.scoper", 16);
+        (0..200_u64).for_each(|count| record_value!("Render", "count", count.into()));
+        drop(record);
+        let trace = crate::Trace::open("results/binary_streaming_test.scoper").unwrap();
+        let counts: Vec<_> = trace.events().iter().map(|event| event.data.clone()).collect();
+        assert_eq!(counts, (0..200_u64).map(|count| crate::EventData::Counter(count.into())).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sink_test()
+    {
+        use std::sync::{Arc, Mutex};
+
+        use crate::{ChromeJsonSink, Event, EventData, RecordingInfo, TraceSink};
+
+        /// Thread names, events and whether it was finished
+        type Received = (Vec<String>, Vec<Event>, bool);
+
+        /// Keeps everything in memory shared with the test
+        #[derive(Clone, Default)]
+        struct Memory(Arc<Mutex<Received>>);
+
+        struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for SharedBuffer
+        {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(buf) }
+
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+
+        impl TraceSink for Memory
+        {
+            fn begin(&mut self, info: &RecordingInfo) -> std::io::Result<()>
+            {
+                assert_eq!(info.metadata["sink"], "memory");
+                Ok(())
+            }
+
+            fn thread_name(&mut self, _header: &str, _thread: u64, name: &str) -> std::io::Result<()>
+            {
+                self.0.lock().unwrap().0.push(name.to_string());
+                Ok(())
+            }
+
+            fn events(&mut self, events: &[Event]) -> std::io::Result<()>
+            {
+                self.0.lock().unwrap().1.extend_from_slice(events);
+                Ok(())
+            }
+
+            fn finish(&mut self) -> std::io::Result<()>
+            {
+                self.0.lock().unwrap().2 = true;
+                Ok(())
+            }
+        }
+
+        /// Fails on the first batch
+        struct Broken;
+
+        impl TraceSink for Broken
+        {
+            fn events(&mut self, _events: &[Event]) -> std::io::Result<()> { Err(std::io::Error::other("broken sink")) }
+        }
+
+        std::fs::create_dir_all("results").unwrap();
+        let memory = Memory::default();
+        let mut record = RecordScope::with_sink(memory.clone());
+        // Failing sinks do not keep the others from being completed
+        record.add_sink(Broken);
+        record.add_output("results/missing directory/sink_test.json");
+        // A chrome trace in a buffer, it is only read back after dropping the recording
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        record.add_sink(ChromeJsonSink::new(SharedBuffer(buffer.clone())));
+        record.add_output("results/sink_test.json");
+        record.add_meta_data("sink".to_string(), &"memory").unwrap();
+        record.name_thread(std::thread::current().id(), "", "Main".to_string());
+        for index in 0..3_u32
+        {
+            record_scope!("outer", index = index);
+            {
+                record_scope!("inner");
+            }
+        }
+        drop(record);
+
+        let (names, events, finished) = &*memory.0.lock().unwrap();
+        assert!(finished);
+        assert_eq!(names, &["Main"]);
+        assert_eq!(events.iter().filter(|event| event.name == "outer").count(), 3);
+        assert!(events.iter().all(|event| matches!(event.data, EventData::Scope { .. })));
+        assert!(events.is_sorted_by_key(|event| event.time));
+        // Every sink got the same recording
+        let buffered: serde_json::Value = serde_json::from_slice(&buffer.lock().unwrap()).unwrap();
+        let file: serde_json::Value = serde_json::from_reader(std::fs::File::open("results/sink_test.json").unwrap()).unwrap();
+        assert_eq!(buffered, file);
+        assert_eq!(trace_events("results/sink_test.json").len(), events.len() + 1);
+    }
+
+    #[record]
+    fn load_level(level: u32)
+    {
+        crate::record_arg("level", level);
+        sleep(Duration::from_millis(5));
+    }
+
+    #[test]
+    fn capture_test()
+    {
+        let trace = crate::capture(|| {
+            record_scope!("startup");
+            load_level(1);
+            record_value!("", "loaded", 1_u32.into());
+            record_instant!("ready");
+        });
+
+        let level = trace.find("load_level").unwrap();
+        assert!(level.is_inside("startup"));
+        assert_eq!(level.parent().unwrap().name(), "startup");
+        assert_eq!(trace.count("load_level"), 1);
+        assert_eq!(trace.find("startup").unwrap().children().count(), 1);
+        assert!(matches!(level.arg("level"), Some(crate::Value::UInt(1))));
+        assert!(trace.total_time("load_level") >= Duration::from_millis(5));
+        assert!(trace.total_time("startup") >= trace.total_time("load_level"));
+        assert_eq!(trace.counters("loaded").count(), 1);
+        assert_eq!(trace.instants("ready").count(), 1);
+        assert_eq!(trace.roots().count(), 1);
+
+        // Scopes of different headers on one thread share the call tree
+        let trace = crate::capture(|| {
+            record_scope!("Game", "startup");
+            load_level(2);
+        });
+        assert!(trace.find("load_level").unwrap().is_inside("startup"));
+        assert_eq!(trace.roots().map(|scope| scope.name().to_string()).collect::<Vec<_>>(), ["startup"]);
+    }
+
+    #[test]
+    fn chrome_reader_test()
+    {
+        use crate::{ChromeId, ChromeTrace, Phase};
+
+        std::fs::create_dir_all("results").unwrap();
+        {
+            let mut record = RecordScope::start("results/chrome_reader_test.json.gz");
+            record.name_thread(std::thread::current().id(), "reader", "Main".to_string());
+            record.add_meta_data("run".to_string(), &7).unwrap();
+            record_scope!("reader", "load", size = 3_u32);
+            record_instant!("reader", "loaded", InstantScopeSize::Thread);
+        }
+        let trace = ChromeTrace::load("results/chrome_reader_test.json.gz").unwrap();
+        assert_eq!(trace.metadata["run"], 7);
+        assert_eq!(trace.display_time_unit.as_deref(), Some("ms"));
+        let load = trace.events.iter().find(|event| event.name == "load").unwrap();
+        assert_eq!(load.ph, Phase::Complete);
+        assert_eq!(load.pid, ChromeId::Name("reader".to_string()));
+        assert_eq!(load.args["size"], 3);
+        assert!(load.dur.is_some());
+        assert_eq!(trace.thread_name(&load.pid, &load.tid), Some("Main"));
+        let loaded = trace.events.iter().find(|event| event.name == "loaded").unwrap();
+        assert_eq!(loaded.extra["s"], "t");
+
+        // Bare array form of other tools, the closing bracket may be missing
+        let array: ChromeTrace = r#"[{"name": "a", "ph": "B", "pid": 1, "tid": 2, "ts": 1.5},
+            {"name": "a", "ph": "E", "pid": 1, "tid": 2, "ts": 4.25},"#
+            .parse()
+            .unwrap();
+        assert_eq!(array.events.len(), 2);
+        assert_eq!(array.events[1].ph, Phase::End);
+        assert!((array.events[1].ts - 4.25).abs() < f64::EPSILON);
+        assert_eq!(array.events[0].tid, ChromeId::Number(2));
+    }
+
+    #[test]
+    fn trace_tools_test()
+    {
+        use crate::Trace;
+
+        std::fs::create_dir_all("results").unwrap();
+        {
+            let mut record = RecordScope::start("results/trace_tools_test.json");
+            record.name_thread(std::thread::current().id(), "tools", "Main".to_string());
+            for index in 0..4_u32
+            {
+                record_scope!("tools", "step", index = index);
+                record_value!("tools", "progress", index.into());
+                let _flow = record_flow!("tools", "hand over");
+                {
+                    record_scope!("tools", "inner");
+                    sleep(Duration::from_millis(2));
+                }
+            }
+        }
+        let mut trace = Trace::open("results/trace_tools_test.json").unwrap();
+        let stats = trace.scope_stats();
+        let step = stats.iter().find(|stats| stats.name == "step").unwrap();
+        assert_eq!(step.count, 4);
+        assert!(step.self_time < step.total && step.p50 <= step.p95 && step.p95 <= step.max);
+        assert_eq!(stats.iter().find(|stats| stats.name == "inner").unwrap().count, 4);
+
+        // Reading a json trace and writing it again gives the same file
+        trace.write("results/trace_tools_copy.json").unwrap();
+        let events = |path| {
+            let mut events: Vec<_> = trace_events(path).iter().map(serde_json::Value::to_string).collect();
+            events.sort();
+            events
+        };
+        assert_eq!(events("results/trace_tools_test.json"), events("results/trace_tools_copy.json"));
+
+        let mut other = Trace::open("results/trace_tools_copy.json").unwrap();
+        other.rename_headers(|header| format!("copy/{header}"));
+        trace.merge(other);
+        trace.retain(|event| event.name == "step");
+        assert_eq!(trace.event_count(), 8);
+        assert_eq!(trace.thread_name("copy/tools", trace.events()[0].thread), "Main");
+    }
+
+    #[test]
+    fn diff_test()
+    {
+        use crate::{Metric, Trace, diff};
+
+        fn run(path: &str, millis: u64)
+        {
+            let _record = RecordScope::start(path);
+            for _ in 0..3
+            {
+                record_scope!("diff", "frame");
+                {
+                    record_scope!("diff", "physics");
+                    sleep(Duration::from_millis(millis));
+                }
+            }
+            if millis > 2
+            {
+                record_scope!("diff", "new work");
+            }
+        }
+
+        std::fs::create_dir_all("results").unwrap();
+        run("results/diff_before.json", 2);
+        run("results/diff_after.json", 8);
+        let before = Trace::open("results/diff_before.json").unwrap();
+        let after = Trace::open("results/diff_after.json").unwrap();
+        let diffs = diff(&before, &after, Metric::Total);
+
+        // Biggest regression first, frame and physics grew by about the same
+        assert!(diffs.windows(2).all(|pair| pair[0].delta(Metric::Total) >= pair[1].delta(Metric::Total)));
+        assert!(["frame", "physics"].contains(&diffs[0].name.as_str()));
+        let physics = diffs.iter().find(|diff| diff.name == "physics").unwrap();
+        assert_eq!(physics.delta(Metric::Count), 0);
+        assert!(!physics.regressed(Metric::Count, 0.0, 0));
+        assert!(physics.relative(Metric::P50).unwrap() > 1.0);
+        assert!(physics.regressed(Metric::SelfTime, 50.0, 0));
+        assert!(!physics.regressed(Metric::Total, 50.0, Duration::from_secs(1).as_nanos().try_into().unwrap()));
+        let new = diffs.iter().find(|diff| diff.name == "new work").unwrap();
+        assert!(new.before.is_none() && new.relative(Metric::Total).is_none());
+        // The minimum is inclusive and counted in calls for the count
+        assert!(new.regressed(Metric::Count, 50.0, 1) && !new.regressed(Metric::Count, 50.0, 2));
+        assert!(diff(&before, &before, Metric::P95).iter().all(|diff| !diff.regressed(Metric::P95, 0.0, 0)));
+    }
 }